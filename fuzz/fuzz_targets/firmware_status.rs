@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sev_iocuddle::error::{Error, ErrorCode};
+
+use std::convert::TryFrom;
+
+fuzz_target!(|status: u32| {
+    match Error::try_from(status) {
+        Ok(error) => {
+            assert_eq!(error.code(), Some(status));
+            let _ = error.to_string();
+            let _ = error.kind();
+            let _ = error.hint();
+            let _ = ErrorCode::from(&error);
+        }
+        Err(raw) => assert_eq!((status, raw), (0, 0)),
+    }
+
+    let _ = sev_iocuddle::error::sev_iocuddle_error_code(status);
+});
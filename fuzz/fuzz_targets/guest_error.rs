@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sev_iocuddle::error::{Error, GuestError, Indeterminate};
+
+use std::io;
+
+fuzz_target!(|exitinfo2: u64| {
+    let error = GuestError::new(io::Error::from_raw_os_error(5), exitinfo2);
+
+    assert_eq!(error.status(), exitinfo2 as u32);
+    assert_eq!(error.firmware_error().is_some(), exitinfo2 as u32 != 0);
+    assert_eq!(error.vmm_error().is_some(), exitinfo2 >> 32 != 0);
+    let _ = error.to_string();
+
+    let _: Indeterminate<Error> = error.into();
+});
@@ -0,0 +1,230 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// The GUID-keyed certificate table returned alongside extended attestation reports.
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result};
+
+/// The GUID of the versioned chip endorsement key certificate.
+pub const VCEK_GUID: [u8; 16] = guid(0x63da758d, 0xe664, 0x4564, 0xadc5f4b93be8accd);
+
+/// The GUID of the versioned loaded endorsement key certificate.
+pub const VLEK_GUID: [u8; 16] = guid(0xa8074bc2, 0xa25a, 0x483e, 0xaae639c045a0b8a1);
+
+/// The GUID of the AMD SEV signing key certificate.
+pub const ASK_GUID: [u8; 16] = guid(0x4ab7b379, 0xbbac, 0x4fe4, 0xa02f05aef327c782);
+
+/// The GUID of the AMD root key certificate.
+pub const ARK_GUID: [u8; 16] = guid(0xc0b406a4, 0xa803, 0x4952, 0x97433fb6014cd0ae);
+
+/// The GUID of the certificate revocation list.
+pub const CRL_GUID: [u8; 16] = guid(0x92f81bc3, 0x5811, 0x4d3d, 0x97ffd19f88dc67ea);
+
+/// Lay out a GUID in its canonical (big-endian) byte order.
+const fn guid(a: u32, b: u16, c: u16, d: u64) -> [u8; 16] {
+    let a = a.to_be_bytes();
+    let b = b.to_be_bytes();
+    let c = c.to_be_bytes();
+    let d = d.to_be_bytes();
+
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6],
+        d[7],
+    ]
+}
+
+/// The size of a single entry in the table: a GUID, an offset and a length.
+const ENTRY_SIZE: usize = 24;
+
+/// A certificate stored in a [`CertTable`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertEntry {
+    /// The GUID identifying the kind of certificate.
+    pub guid: [u8; 16],
+
+    /// The raw certificate data.
+    pub data: Vec<u8>,
+}
+
+/// A table of certificates keyed by GUID.
+///
+/// The table starts with a list of entries, each holding a GUID and the
+/// offset and length of the certificate within the table, terminated by an
+/// entry which is entirely zero. The certificates follow the list of entries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CertTable {
+    entries: Vec<CertEntry>,
+}
+
+impl CertTable {
+    /// Create a new `CertTable` holding the given certificates.
+    pub fn new(entries: Vec<CertEntry>) -> Self {
+        Self { entries }
+    }
+
+    /// Parse a certificate table, validating that every entry refers to data
+    /// which lies within the table and after the list of entries.
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let invalid = |msg| Error::new(ErrorKind::InvalidData, msg);
+        let mut entries = Vec::new();
+
+        for raw in blob.chunks(ENTRY_SIZE) {
+            if raw.len() < ENTRY_SIZE {
+                return Err(invalid("unterminated certificate table"));
+            }
+
+            let guid: [u8; 16] = raw[..16].try_into().unwrap();
+            let offset = u32::from_le_bytes(raw[16..20].try_into().unwrap()) as usize;
+            let length = u32::from_le_bytes(raw[20..24].try_into().unwrap()) as usize;
+
+            if guid == [0; 16] && offset == 0 && length == 0 {
+                let table_end = (entries.len() + 1) * ENTRY_SIZE;
+                return Self::collect(blob, table_end, entries);
+            }
+
+            entries.push((guid, offset, length));
+        }
+
+        Err(invalid("unterminated certificate table"))
+    }
+
+    fn collect(blob: &[u8], table_end: usize, raw: Vec<([u8; 16], usize, usize)>) -> Result<Self> {
+        let mut entries = Vec::with_capacity(raw.len());
+
+        for (guid, offset, length) in raw {
+            let end = offset
+                .checked_add(length)
+                .filter(|end| offset >= table_end && *end <= blob.len())
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "certificate lies outside of table")
+                })?;
+
+            entries.push(CertEntry {
+                guid,
+                data: blob[offset..end].to_vec(),
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Serialize the table, placing the certificates directly after the list
+    /// of entries in the order they are held.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let too_large = || Error::new(ErrorKind::InvalidInput, "certificate table too large");
+        let mut offset = (self.entries.len() + 1) * ENTRY_SIZE;
+        let mut table = Vec::with_capacity(offset);
+        let mut data = Vec::new();
+
+        for entry in &self.entries {
+            let off: u32 = offset.try_into().map_err(|_| too_large())?;
+            let len: u32 = entry.data.len().try_into().map_err(|_| too_large())?;
+
+            table.extend_from_slice(&entry.guid);
+            table.extend_from_slice(&off.to_le_bytes());
+            table.extend_from_slice(&len.to_le_bytes());
+            data.extend_from_slice(&entry.data);
+            offset += entry.data.len();
+        }
+
+        table.extend_from_slice(&[0; ENTRY_SIZE]);
+        table.extend_from_slice(&data);
+        Ok(table)
+    }
+
+    /// Iterate over the certificates in the table.
+    pub fn iter(&self) -> impl Iterator<Item = &CertEntry> {
+        self.entries.iter()
+    }
+
+    /// Look up the first certificate with the given GUID.
+    pub fn get(&self, guid: &[u8; 16]) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|entry| &entry.guid == guid)
+            .map(|entry| &entry.data[..])
+    }
+
+    /// The versioned chip endorsement key certificate, if present.
+    pub fn vcek(&self) -> Option<&[u8]> {
+        self.get(&VCEK_GUID)
+    }
+
+    /// The versioned loaded endorsement key certificate, if present.
+    pub fn vlek(&self) -> Option<&[u8]> {
+        self.get(&VLEK_GUID)
+    }
+
+    /// The AMD SEV signing key certificate, if present.
+    pub fn ask(&self) -> Option<&[u8]> {
+        self.get(&ASK_GUID)
+    }
+
+    /// The AMD root key certificate, if present.
+    pub fn ark(&self) -> Option<&[u8]> {
+        self.get(&ARK_GUID)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(guid: [u8; 16], offset: u32, length: u32) -> Vec<u8> {
+        let mut raw = guid.to_vec();
+        raw.extend_from_slice(&offset.to_le_bytes());
+        raw.extend_from_slice(&length.to_le_bytes());
+        raw
+    }
+
+    #[test]
+    fn round_trip() {
+        let table = CertTable::new(vec![
+            CertEntry {
+                guid: VCEK_GUID,
+                data: vec![1, 2, 3],
+            },
+            CertEntry {
+                guid: ASK_GUID,
+                data: vec![4, 5],
+            },
+        ]);
+
+        let blob = table.to_bytes().unwrap();
+        assert_eq!(blob.len(), 3 * ENTRY_SIZE + 5);
+        assert_eq!(blob[..ENTRY_SIZE], entry(VCEK_GUID, 72, 3)[..]);
+        assert_eq!(blob[ENTRY_SIZE..2 * ENTRY_SIZE], entry(ASK_GUID, 75, 2)[..]);
+
+        let parsed = CertTable::parse(&blob).unwrap();
+        assert_eq!(parsed, table);
+        assert_eq!(parsed.vcek(), Some(&[1, 2, 3][..]));
+        assert_eq!(parsed.ask(), Some(&[4, 5][..]));
+        assert_eq!(parsed.ark(), None);
+    }
+
+    #[test]
+    fn rejects_offsets_outside_table() {
+        let terminator = [0; ENTRY_SIZE];
+
+        let mut past_end = entry(VCEK_GUID, 48, 4);
+        past_end.extend_from_slice(&terminator);
+        past_end.extend_from_slice(&[1, 2, 3]);
+
+        let mut in_entries = entry(VCEK_GUID, 0, 4);
+        in_entries.extend_from_slice(&terminator);
+
+        let mut too_long = entry(VCEK_GUID, 48, u32::MAX);
+        too_long.extend_from_slice(&terminator);
+
+        for blob in [past_end, in_entries, too_long] {
+            let err = CertTable::parse(&blob).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_unterminated_table() {
+        let blob = entry(VCEK_GUID, 24, 0);
+        let err = CertTable::parse(&blob).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
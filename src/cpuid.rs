@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// Detection of the memory encryption features of the processor through CPUID.
+use bitflags::bitflags;
+
+use std::arch::x86_64::__cpuid;
+
+/// The CPUID leaf reporting the extended leaves which are supported.
+const EXTENDED_LEAVES: u32 = 0x8000_0000;
+
+/// The CPUID leaf describing the memory encryption features of AMD processors.
+pub const LEAF: u32 = 0x8000_001F;
+
+bitflags! {
+    /// The memory encryption features reported in `EAX` of CPUID leaf
+    /// `0x8000001F`.
+    #[derive(Default)]
+    pub struct Features: u32 {
+        /// Secure Memory Encryption.
+        const SME = 1 << 0;
+
+        /// Secure Encrypted Virtualization.
+        const SEV = 1 << 1;
+
+        /// The page flush MSR is available.
+        const PAGE_FLUSH_MSR = 1 << 2;
+
+        /// SEV Encrypted State.
+        const SEV_ES = 1 << 3;
+
+        /// SEV Secure Nested Paging.
+        const SNP = 1 << 4;
+
+        /// Virtual Machine Privilege Levels.
+        const VMPL = 1 << 5;
+
+        /// The `RMPQUERY` instruction.
+        const RMPQUERY = 1 << 6;
+
+        /// VMPL supervisor shadow stacks.
+        const VMPL_SSS = 1 << 7;
+
+        /// Secure TSC.
+        const SECURE_TSC = 1 << 8;
+
+        /// Virtual TSC_AUX.
+        const TSC_AUX_VIRTUALIZATION = 1 << 9;
+
+        /// Hardware cache coherency across encryption domains.
+        const HW_ENFORCED_CACHE_COHERENCY = 1 << 10;
+
+        /// SEV guests may only run in 64-bit mode.
+        const HOST_64BIT_ONLY = 1 << 11;
+
+        /// Restricted injection of interrupts into SEV-SNP guests.
+        const RESTRICTED_INJECTION = 1 << 12;
+
+        /// Alternate injection of interrupts into SEV-SNP guests.
+        const ALTERNATE_INJECTION = 1 << 13;
+
+        /// Debug registers of SEV-ES guests are swapped on world switch.
+        const DEBUG_SWAP = 1 << 14;
+
+        /// The hypervisor may be prevented from issuing `IBS` to guests.
+        const PREVENT_HOST_IBS = 1 << 15;
+
+        /// Virtual Transparent Encryption.
+        const VTE = 1 << 16;
+    }
+}
+
+/// The memory encryption capabilities of the processor, as reported by CPUID
+/// leaf `0x8000001F`.
+///
+/// Inside a guest, the hypervisor controls which features are reported, so
+/// this is a sanity check of the environment rather than a proof of it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryEncryption {
+    eax: u32,
+    ebx: u32,
+    ecx: u32,
+    edx: u32,
+}
+
+impl MemoryEncryption {
+    /// Query the processor, returning `None` if it does not implement the
+    /// leaf, such as processors which are not made by AMD.
+    pub fn query() -> Option<Self> {
+        // CPUID is available on every x86_64 processor; `__cpuid` is only
+        // marked unsafe by older toolchains.
+        #[allow(unused_unsafe)]
+        let (max, leaf) = unsafe { (__cpuid(EXTENDED_LEAVES).eax, __cpuid(LEAF)) };
+        if max < LEAF {
+            return None;
+        }
+
+        Some(Self {
+            eax: leaf.eax,
+            ebx: leaf.ebx,
+            ecx: leaf.ecx,
+            edx: leaf.edx,
+        })
+    }
+
+    /// The memory encryption features of the processor.
+    pub fn features(&self) -> Features {
+        Features::from_bits_truncate(self.eax)
+    }
+
+    /// Whether SEV is supported.
+    pub fn sev(&self) -> bool {
+        self.features().contains(Features::SEV)
+    }
+
+    /// Whether SEV-ES is supported.
+    pub fn sev_es(&self) -> bool {
+        self.features().contains(Features::SEV_ES)
+    }
+
+    /// Whether SEV-SNP is supported.
+    pub fn snp(&self) -> bool {
+        self.features().contains(Features::SNP)
+    }
+
+    /// The position of the C-bit, which marks a page as encrypted, in a page
+    /// table entry.
+    pub fn c_bit(&self) -> u8 {
+        (self.ebx & 0x3F) as _
+    }
+
+    /// The number of physical address bits lost when memory encryption is
+    /// enabled.
+    pub fn phys_addr_reduction(&self) -> u8 {
+        ((self.ebx >> 6) & 0x3F) as _
+    }
+
+    /// The number of VMPLs supported.
+    pub fn vmpls(&self) -> u8 {
+        ((self.ebx >> 12) & 0xF) as _
+    }
+
+    /// The number of encrypted guests which may run at once, which is also
+    /// the highest ASID usable by an encrypted guest.
+    pub fn max_asid(&self) -> u32 {
+        self.ecx
+    }
+
+    /// The lowest ASID usable by an SEV guest without SEV-ES; the ASIDs below
+    /// it are reserved for SEV-ES and SEV-SNP guests.
+    pub fn min_sev_asid(&self) -> u32 {
+        self.edx
+    }
+}
@@ -0,0 +1,578 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// A common interface to the devices through which SEV commands are issued.
+use crate::error::{CommandError, GuestError, VmmError};
+use crate::kvm::{capabilities, KvmSevCaps, SevMode};
+
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// A device through which commands of type `C` are issued, such as the SEV
+/// platform firmware, a VM of KVM or the SEV-SNP guest device.
+///
+/// Code written against this trait rather than against a concrete device can
+/// be exercised without SEV hardware by substituting another implementation.
+pub trait SevDevice<C> {
+    /// The error reported when a command fails.
+    type Error;
+
+    /// Issue `cmd` to the device.
+    fn issue_ioctl(&mut self, cmd: &mut C) -> Result<(), Self::Error>;
+}
+
+/// The options with which a device node is opened, for when the default node
+/// is not the right one, such as in containers or test rigs.
+///
+/// The device is opened for reading and writing, and closed on `exec` unless
+/// requested otherwise.
+#[derive(Clone, Debug)]
+pub struct DeviceOptions {
+    path: Option<PathBuf>,
+    nonblocking: bool,
+    cloexec: bool,
+}
+
+impl DeviceOptions {
+    /// Options for opening the default node of a device.
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            nonblocking: false,
+            cloexec: true,
+        }
+    }
+
+    /// Open the device node at `path` instead of the default one.
+    pub fn path(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Open the device with `O_NONBLOCK`.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Close the device when the process calls `exec` (`O_CLOEXEC`).
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut Self {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Open the device node, at `default` unless another path was given.
+    pub(crate) fn open(&self, default: &str) -> io::Result<File> {
+        let mut flags = 0;
+        if self.nonblocking {
+            flags |= libc::O_NONBLOCK;
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(flags)
+            .open(self.path.as_deref().unwrap_or_else(|| Path::new(default)))?;
+
+        // The standard library always opens files with `O_CLOEXEC`.
+        if !self.cloexec {
+            let fd = file.as_raw_fd();
+            let fd_flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+            if fd_flags < 0
+                || unsafe { libc::fcntl(fd, libc::F_SETFD, fd_flags & !libc::FD_CLOEXEC) } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(file)
+    }
+}
+
+impl Default for DeviceOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An error which may go away when the failed command is issued again.
+pub trait Transient {
+    /// Whether issuing the failed command again may succeed.
+    fn is_transient(&self) -> bool;
+
+    /// The least time to wait before issuing the command again, if known.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Whether the kernel failed a command before it reached the firmware for a
+/// reason which does not persist: `EINTR`, `EAGAIN` or `EBUSY`.
+fn is_transient_os(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::ResourceBusy
+    )
+}
+
+impl Transient for CommandError {
+    fn is_transient(&self) -> bool {
+        self.status() == 0 && is_transient_os(self.os_error())
+    }
+}
+
+impl Transient for GuestError {
+    /// Guest requests are also transient when the hypervisor reports that it
+    /// is busy, which it does while it rate limits the guest.
+    fn is_transient(&self) -> bool {
+        match self.vmm_error() {
+            Some(VmmError::Busy) => true,
+            Some(_) => false,
+            None => self.status() == 0 && is_transient_os(self.os_error()),
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        GuestError::retry_after(self)
+    }
+}
+
+/// How often and how patiently a [`Retry`] device issues a command again
+/// after a transient failure.
+///
+/// The delay before each retry doubles, starting from the initial backoff and
+/// capped at the maximum backoff, unless the error asks for a longer one
+/// through [`Transient::retry_after`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy which issues a command up to five times, waiting 10ms before
+    /// the first retry and at most a second before any other.
+    pub fn new() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Issue a command at most `attempts` times, including the first attempt.
+    pub fn attempts(&mut self, attempts: u32) -> &mut Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Wait `backoff` before the first retry.
+    pub fn backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Never wait longer than `max_backoff` before a retry.
+    pub fn max_backoff(&mut self, max_backoff: Duration) -> &mut Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// The delay before the given retry, counting from zero, or `None` if the
+    /// policy allows no further attempts.
+    pub fn delay(&self, retry: u32) -> Option<Duration> {
+        if retry.saturating_add(1) >= self.attempts {
+            return None;
+        }
+
+        let delay = self
+            .backoff
+            .checked_mul(1 << retry.min(31))
+            .unwrap_or(self.max_backoff);
+        Some(delay.min(self.max_backoff))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A device which issues commands again, following a [`RetryPolicy`], when
+/// they fail with a [`Transient`] error, such as guest requests throttled by
+/// the host.
+///
+/// The last error is reported once the policy allows no further attempts.
+#[derive(Debug)]
+pub struct Retry<D> {
+    device: D,
+    policy: RetryPolicy,
+}
+
+impl<D> Retry<D> {
+    /// Retry the commands issued to `device` following `policy`.
+    pub fn new(device: D, policy: RetryPolicy) -> Self {
+        Self { device, policy }
+    }
+
+    /// The policy commands are retried with.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// The device the commands are issued to.
+    pub fn device(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Give back the device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<C, D> SevDevice<C> for Retry<D>
+where
+    D: SevDevice<C>,
+    D::Error: Transient,
+{
+    type Error = D::Error;
+
+    fn issue_ioctl(&mut self, cmd: &mut C) -> Result<(), Self::Error> {
+        let mut retry = 0;
+
+        loop {
+            match self.device.issue_ioctl(cmd) {
+                Err(e) if e.is_transient() => match self.policy.delay(retry) {
+                    Some(delay) => sleep(delay.max(e.retry_after().unwrap_or_default())),
+                    None => return Err(e),
+                },
+                result => return result,
+            }
+
+            retry += 1;
+        }
+    }
+}
+
+/// Whether a device node may be opened by this process.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Availability {
+    /// The node does not exist, usually because the driver is not loaded.
+    Missing,
+
+    /// The node exists, but this process may not open it.
+    Denied,
+
+    /// The node could not be opened for another reason.
+    Error(ErrorKind),
+
+    /// The node may be opened.
+    Available,
+}
+
+impl Availability {
+    /// Check whether the node at `path` may be opened for reading and writing.
+    pub fn of(path: impl AsRef<Path>) -> Self {
+        let open = OpenOptions::new().read(true).write(true).open(path);
+
+        match open {
+            Ok(_) => Availability::Available,
+            Err(e) if e.kind() == ErrorKind::NotFound => Availability::Missing,
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => Availability::Denied,
+            Err(e) => match e.raw_os_error() {
+                // The node exists, but no driver is bound to it.
+                Some(libc::ENODEV) | Some(libc::ENXIO) => Availability::Missing,
+                _ => Availability::Error(e.kind()),
+            },
+        }
+    }
+
+    /// Whether the node may be opened.
+    pub fn is_available(&self) -> bool {
+        *self == Availability::Available
+    }
+}
+
+/// The SEV-related parameters of the `kvm_amd` module, where `None` means the
+/// parameter could not be read, such as when the module is not loaded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KvmAmdParams {
+    /// `/sys/module/kvm_amd/parameters/sev`
+    pub sev: Option<bool>,
+
+    /// `/sys/module/kvm_amd/parameters/sev_es`
+    pub sev_es: Option<bool>,
+
+    /// `/sys/module/kvm_amd/parameters/sev_snp`
+    pub snp: Option<bool>,
+}
+
+impl KvmAmdParams {
+    /// Read the parameters of the loaded `kvm_amd` module.
+    pub fn read() -> Self {
+        let param = |name: &str| {
+            let value = read_to_string(format!("/sys/module/kvm_amd/parameters/{}", name)).ok()?;
+            match value.trim() {
+                "Y" | "y" | "1" => Some(true),
+                "N" | "n" | "0" => Some(false),
+                _ => None,
+            }
+        };
+
+        Self {
+            sev: param("sev"),
+            sev_es: param("sev_es"),
+            snp: param("sev_snp"),
+        }
+    }
+}
+
+/// The support for SEV found on this machine by [`probe`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Support {
+    /// The SEV platform firmware device, `/dev/sev`.
+    pub firmware: Availability,
+
+    /// The SEV-SNP guest device, `/dev/sev-guest`.
+    pub guest_device: Availability,
+
+    /// The capabilities of KVM, or `None` if `/dev/kvm` could not be opened.
+    pub kvm: Option<KvmSevCaps>,
+
+    /// The parameters of the `kvm_amd` module.
+    pub params: KvmAmdParams,
+}
+
+impl Support {
+    /// Whether VMs of the given mode may be launched on this machine.
+    pub fn supports(&self, mode: SevMode) -> bool {
+        self.missing(mode).is_none()
+    }
+
+    /// Whether SEV VMs may be launched on this machine.
+    pub fn sev(&self) -> bool {
+        self.supports(SevMode::Sev)
+    }
+
+    /// Whether SEV-ES VMs may be launched on this machine.
+    pub fn sev_es(&self) -> bool {
+        self.supports(SevMode::SevEs)
+    }
+
+    /// Whether SEV-SNP VMs may be launched on this machine.
+    pub fn snp(&self) -> bool {
+        self.supports(SevMode::Snp)
+    }
+
+    /// Whether this machine is an SEV-SNP guest which may request attestation
+    /// reports and derived keys.
+    pub fn guest(&self) -> bool {
+        self.guest_device.is_available()
+    }
+
+    /// The first thing which prevents VMs of the given mode from being
+    /// launched on this machine, if any.
+    ///
+    /// Kernels which predate `KVM_CAP_VM_TYPES` advertise no SEV support
+    /// through KVM, so the module parameters are consulted for SEV and SEV-ES
+    /// instead. SEV-SNP always requires the dedicated VM type.
+    pub fn missing(&self, mode: SevMode) -> Option<Missing> {
+        match self.firmware {
+            Availability::Missing => return Some(Missing::Firmware),
+            Availability::Denied => return Some(Missing::FirmwareAccess),
+            Availability::Error(kind) => return Some(Missing::FirmwareError(kind)),
+            Availability::Available => {}
+        }
+
+        let caps = match self.kvm {
+            Some(caps) => caps,
+            None => return Some(Missing::Kvm),
+        };
+
+        let (advertised, param, name) = match mode {
+            SevMode::Sev => (caps.sev, self.params.sev, "sev"),
+            SevMode::SevEs => (caps.sev_es, self.params.sev_es, "sev_es"),
+            SevMode::Snp => (caps.snp, self.params.snp, "sev_snp"),
+        };
+
+        match param {
+            _ if advertised => None,
+            Some(true) if mode != SevMode::Snp => None,
+            Some(false) => Some(Missing::ModuleParam(name)),
+            _ => Some(Missing::VmType(mode)),
+        }
+    }
+}
+
+/// What prevents VMs of some mode from being launched, as reported by
+/// [`Support::missing`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Missing {
+    /// `/dev/sev` does not exist.
+    Firmware,
+
+    /// `/dev/sev` may not be opened by this process.
+    FirmwareAccess,
+
+    /// `/dev/sev` could not be opened for another reason.
+    FirmwareError(ErrorKind),
+
+    /// `/dev/kvm` may not be opened by this process.
+    Kvm,
+
+    /// The given parameter of the `kvm_amd` module is disabled.
+    ModuleParam(&'static str),
+
+    /// KVM does not advertise the VM type of the given mode.
+    VmType(SevMode),
+}
+
+impl std::fmt::Display for Missing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Missing::Firmware => write!(f, "/dev/sev does not exist; is the ccp module loaded?"),
+            Missing::FirmwareAccess => write!(f, "/dev/sev may not be opened by this user"),
+            Missing::FirmwareError(kind) => write!(f, "/dev/sev could not be opened: {}", kind),
+            Missing::Kvm => write!(f, "/dev/kvm may not be opened; is kvm_amd loaded?"),
+            Missing::ModuleParam(name) => {
+                write!(f, "the {} parameter of kvm_amd is disabled", name)
+            }
+            Missing::VmType(mode) => write!(f, "KVM does not support {:?} VMs", mode),
+        }
+    }
+}
+
+/// Probe the SEV support of this machine: the firmware and guest devices, the
+/// SEV capabilities of KVM and the parameters of the `kvm_amd` module.
+///
+/// Probing never fails, so that installers and command-line tools can
+/// explain exactly what is missing through [`Support::missing`].
+pub fn probe() -> Support {
+    let kvm = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .ok()
+        .and_then(|kvm| capabilities(&kvm).ok());
+
+    Support {
+        firmware: Availability::of("/dev/sev"),
+        guest_device: Availability::of("/dev/sev-guest"),
+        kvm,
+        params: KvmAmdParams::read(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    /// A device which fails with each of the given errors in turn.
+    struct Flaky {
+        errors: VecDeque<CommandError>,
+        calls: usize,
+    }
+
+    impl Flaky {
+        fn new(errors: impl IntoIterator<Item = CommandError>) -> Self {
+            Self {
+                errors: errors.into_iter().collect(),
+                calls: 0,
+            }
+        }
+    }
+
+    impl SevDevice<()> for Flaky {
+        type Error = CommandError;
+
+        fn issue_ioctl(&mut self, _: &mut ()) -> Result<(), CommandError> {
+            self.calls += 1;
+            self.errors.pop_front().map_or(Ok(()), Err)
+        }
+    }
+
+    fn os(kind: ErrorKind) -> CommandError {
+        CommandError::new(kind.into(), 0)
+    }
+
+    #[test]
+    fn retry_policy_delay() {
+        let ms = Duration::from_millis;
+        let mut policy = RetryPolicy::new();
+        policy.backoff(ms(10)).max_backoff(ms(50)).attempts(6);
+
+        let delays: Vec<_> = (0..6).map(|retry| policy.delay(retry)).collect();
+        let expected = [
+            Some(ms(10)),
+            Some(ms(20)),
+            Some(ms(40)),
+            Some(ms(50)),
+            Some(ms(50)),
+            None,
+        ];
+        assert_eq!(delays, expected);
+
+        // The backoff saturates rather than overflowing.
+        policy.attempts(u32::MAX);
+        assert_eq!(policy.delay(100), Some(ms(50)));
+        assert_eq!(policy.delay(u32::MAX), None);
+
+        assert_eq!(policy.attempts(1).delay(0), None);
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(os(ErrorKind::Interrupted).is_transient());
+        assert!(os(ErrorKind::WouldBlock).is_transient());
+        assert!(os(ErrorKind::ResourceBusy).is_transient());
+        assert!(!os(ErrorKind::InvalidInput).is_transient());
+
+        // A firmware error persists however often the command is issued.
+        let fw = CommandError::new(ErrorKind::Interrupted.into(), 0x10);
+        assert!(!fw.is_transient());
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        let mut policy = RetryPolicy::new();
+        policy.backoff(Duration::ZERO).attempts(3);
+
+        let flaky = Flaky::new(vec![os(ErrorKind::Interrupted), os(ErrorKind::WouldBlock)]);
+        let mut retry = Retry::new(flaky, policy);
+        retry.issue_ioctl(&mut ()).unwrap();
+        assert_eq!(retry.device().calls, 3);
+
+        // The last error is reported once the attempts are used up.
+        let errors = (0..4).map(|_| os(ErrorKind::ResourceBusy));
+        let mut retry = Retry::new(Flaky::new(errors), policy);
+        let err = retry.issue_ioctl(&mut ()).unwrap_err();
+        assert_eq!(err.os_error().kind(), ErrorKind::ResourceBusy);
+        assert_eq!(retry.device().calls, 3);
+
+        // Other errors are reported at once.
+        let flaky = Flaky::new(vec![os(ErrorKind::InvalidInput)]);
+        let mut retry = Retry::new(flaky, policy);
+        assert!(retry.issue_ioctl(&mut ()).is_err());
+        assert_eq!(retry.into_inner().calls, 1);
+    }
+
+    #[test]
+    fn availability() {
+        assert_eq!(Availability::of("/dev/null"), Availability::Available);
+        assert_eq!(Availability::of("/nonexistent/sev"), Availability::Missing);
+        assert_eq!(
+            Availability::of("/"),
+            Availability::Error(ErrorKind::IsADirectory)
+        );
+    }
+}
@@ -1,7 +1,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 /// Types of potential errors returned by the OS when issuing ioctls to the SEV platform.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::time::Duration;
 use std::{error, io};
 
 /// There are a number of error conditions that can occur between this
@@ -9,6 +14,7 @@ use std::{error, io};
 /// been enumerated; however, there is a possibility that some error
 /// conditions are not encapsulated here.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Indeterminate<T: Debug> {
     /// The error condition is known.
     Known(T),
@@ -17,12 +23,210 @@ pub enum Indeterminate<T: Debug> {
     Unknown,
 }
 
+impl<T: Debug> Indeterminate<T> {
+    /// Whether the condition is known.
+    pub fn is_known(&self) -> bool {
+        matches!(self, Indeterminate::Known(_))
+    }
+
+    /// The condition, if it is known.
+    pub fn known(self) -> Option<T> {
+        match self {
+            Indeterminate::Known(t) => Some(t),
+            Indeterminate::Unknown => None,
+        }
+    }
+
+    /// Borrow the condition, if it is known.
+    pub fn as_known(&self) -> Option<&T> {
+        match self {
+            Indeterminate::Known(t) => Some(t),
+            Indeterminate::Unknown => None,
+        }
+    }
+
+    /// Transform a known condition, leaving an unknown one as it is.
+    pub fn map<U: Debug, F: FnOnce(T) -> U>(self, f: F) -> Indeterminate<U> {
+        match self {
+            Indeterminate::Known(t) => Indeterminate::Known(f(t)),
+            Indeterminate::Unknown => Indeterminate::Unknown,
+        }
+    }
+
+    /// The condition if it is known, or `err` otherwise.
+    pub fn ok_or<E>(self, err: E) -> Result<T, E> {
+        self.known().ok_or(err)
+    }
+
+    /// The condition if it is known, or the result of `err` otherwise.
+    pub fn ok_or_else<E, F: FnOnce() -> E>(self, err: F) -> Result<T, E> {
+        self.known().ok_or_else(err)
+    }
+}
+
+impl<T: Debug> From<T> for Indeterminate<T> {
+    #[inline]
+    fn from(t: T) -> Indeterminate<T> {
+        Indeterminate::Known(t)
+    }
+}
+
+impl<T: Debug + std::fmt::Display> std::fmt::Display for Indeterminate<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Indeterminate::Known(e) => write!(f, "{}", e),
+            Indeterminate::Unknown => write!(f, "Unknown error"),
+        }
+    }
+}
+
+impl<T: error::Error + 'static> error::Error for Indeterminate<T> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Indeterminate::Known(e) => e.source(),
+            Indeterminate::Unknown => None,
+        }
+    }
+}
+
+/// The status codes reported by the firmware, spelled as in the Linux kernel:
+/// include/uapi/linux/psp-sev.h
+///
+/// Every code other than `SEV_RET_SUCCESS` converts to an [`Error`] through
+/// `Error::try_from`, and [`Error::code`] converts back.
+pub mod codes {
+    /// The status of a command which succeeded.
+    pub const SEV_RET_SUCCESS: u32 = 0x0000;
+
+    /// Decoded as [`Error::InvalidPlatformState`](super::Error::InvalidPlatformState).
+    pub const SEV_RET_INVALID_PLATFORM_STATE: u32 = 0x0001;
+
+    /// Decoded as [`Error::InvalidGuestState`](super::Error::InvalidGuestState).
+    pub const SEV_RET_INVALID_GUEST_STATE: u32 = 0x0002;
+
+    /// Decoded as [`Error::InvalidConfig`](super::Error::InvalidConfig).
+    pub const SEV_RET_INVALID_CONFIG: u32 = 0x0003;
+
+    /// Decoded as [`Error::InvalidLen`](super::Error::InvalidLen).
+    pub const SEV_RET_INVALID_LEN: u32 = 0x0004;
+
+    /// Decoded as [`Error::AlreadyOwned`](super::Error::AlreadyOwned).
+    pub const SEV_RET_ALREADY_OWNED: u32 = 0x0005;
+
+    /// Decoded as [`Error::InvalidCertificate`](super::Error::InvalidCertificate).
+    pub const SEV_RET_INVALID_CERTIFICATE: u32 = 0x0006;
+
+    /// Decoded as [`Error::PolicyFailure`](super::Error::PolicyFailure).
+    pub const SEV_RET_POLICY_FAILURE: u32 = 0x0007;
+
+    /// Decoded as [`Error::Inactive`](super::Error::Inactive).
+    pub const SEV_RET_INACTIVE: u32 = 0x0008;
+
+    /// Decoded as [`Error::InvalidAddress`](super::Error::InvalidAddress).
+    pub const SEV_RET_INVALID_ADDRESS: u32 = 0x0009;
+
+    /// Decoded as [`Error::BadSignature`](super::Error::BadSignature).
+    pub const SEV_RET_BAD_SIGNATURE: u32 = 0x000a;
+
+    /// Decoded as [`Error::BadMeasurement`](super::Error::BadMeasurement).
+    pub const SEV_RET_BAD_MEASUREMENT: u32 = 0x000b;
+
+    /// Decoded as [`Error::AsidOwned`](super::Error::AsidOwned).
+    pub const SEV_RET_ASID_OWNED: u32 = 0x000c;
+
+    /// Decoded as [`Error::InvalidAsid`](super::Error::InvalidAsid).
+    pub const SEV_RET_INVALID_ASID: u32 = 0x000d;
+
+    /// Decoded as [`Error::WbinvdRequired`](super::Error::WbinvdRequired).
+    pub const SEV_RET_WBINVD_REQUIRED: u32 = 0x000e;
+
+    /// Decoded as [`Error::DfFlushRequired`](super::Error::DfFlushRequired).
+    pub const SEV_RET_DFFLUSH_REQUIRED: u32 = 0x000f;
+
+    /// Decoded as [`Error::InvalidGuest`](super::Error::InvalidGuest).
+    pub const SEV_RET_INVALID_GUEST: u32 = 0x0010;
+
+    /// Decoded as [`Error::InvalidCommand`](super::Error::InvalidCommand).
+    pub const SEV_RET_INVALID_COMMAND: u32 = 0x0011;
+
+    /// Decoded as [`Error::Active`](super::Error::Active).
+    pub const SEV_RET_ACTIVE: u32 = 0x0012;
+
+    /// Decoded as [`Error::HardwarePlatform`](super::Error::HardwarePlatform).
+    pub const SEV_RET_HWSEV_RET_PLATFORM: u32 = 0x0013;
+
+    /// Decoded as [`Error::HardwareUnsafe`](super::Error::HardwareUnsafe).
+    pub const SEV_RET_HWSEV_RET_UNSAFE: u32 = 0x0014;
+
+    /// Decoded as [`Error::Unsupported`](super::Error::Unsupported).
+    pub const SEV_RET_UNSUPPORTED: u32 = 0x0015;
+
+    /// Decoded as [`Error::InvalidParam`](super::Error::InvalidParam).
+    pub const SEV_RET_INVALID_PARAM: u32 = 0x0016;
+
+    /// Decoded as [`Error::ResourceLimit`](super::Error::ResourceLimit).
+    pub const SEV_RET_RESOURCE_LIMIT: u32 = 0x0017;
+
+    /// Decoded as [`Error::SecureDataInvalid`](super::Error::SecureDataInvalid).
+    pub const SEV_RET_SECURE_DATA_INVALID: u32 = 0x0018;
+
+    /// Decoded as [`Error::InvalidPageSize`](super::Error::InvalidPageSize).
+    pub const SEV_RET_INVALID_PAGE_SIZE: u32 = 0x0019;
+
+    /// Decoded as [`Error::InvalidPageState`](super::Error::InvalidPageState).
+    pub const SEV_RET_INVALID_PAGE_STATE: u32 = 0x001a;
+
+    /// Decoded as [`Error::InvalidMdataEntry`](super::Error::InvalidMdataEntry).
+    pub const SEV_RET_INVALID_MDATA_ENTRY: u32 = 0x001b;
+
+    /// Decoded as [`Error::InvalidPageOwner`](super::Error::InvalidPageOwner).
+    pub const SEV_RET_INVALID_PAGE_OWNER: u32 = 0x001c;
+
+    /// Decoded as [`Error::AeadOverflow`](super::Error::AeadOverflow).
+    pub const SEV_RET_INVALID_PAGE_AEAD_OFLOW: u32 = 0x001d;
+
+    /// Decoded as [`Error::RingBufferExit`](super::Error::RingBufferExit).
+    pub const SEV_RET_EXIT_RING_BUFFER: u32 = 0x001f;
+
+    /// Decoded as [`Error::RmpInitRequired`](super::Error::RmpInitRequired).
+    pub const SEV_RET_RMP_INIT_REQUIRED: u32 = 0x0020;
+
+    /// Decoded as [`Error::BadSvn`](super::Error::BadSvn).
+    pub const SEV_RET_BAD_SVN: u32 = 0x0021;
+
+    /// Decoded as [`Error::BadVersion`](super::Error::BadVersion).
+    pub const SEV_RET_BAD_VERSION: u32 = 0x0022;
+
+    /// Decoded as [`Error::ShutdownRequired`](super::Error::ShutdownRequired).
+    pub const SEV_RET_SHUTDOWN_REQUIRED: u32 = 0x0023;
+
+    /// Decoded as [`Error::UpdateFailed`](super::Error::UpdateFailed).
+    pub const SEV_RET_UPDATE_FAILED: u32 = 0x0024;
+
+    /// Decoded as [`Error::RestoreRequired`](super::Error::RestoreRequired).
+    pub const SEV_RET_RESTORE_REQUIRED: u32 = 0x0025;
+
+    /// Decoded as [`Error::RmpInitializationFailed`](super::Error::RmpInitializationFailed).
+    pub const SEV_RET_RMP_INITIALIZATION_FAILED: u32 = 0x0026;
+
+    /// Decoded as [`Error::InvalidKey`](super::Error::InvalidKey).
+    pub const SEV_RET_INVALID_KEY: u32 = 0x0027;
+}
+
 /// Error conditions returned by the SEV platform or by layers above it
 /// (i.e., the Linux kernel).
 ///
 /// These error conditions are documented in the AMD SEV API spec, but
-/// their documentation has been copied here for completeness.
+/// their documentation has been copied here for completeness. The variants
+/// other than `IoError` cover every status code the spec defines for the
+/// legacy SEV commands, from `0x01` (`INVALID_PLATFORM_STATE`) through `0x18`
+/// (`SECURE_DATA_INVALID`), as well as those the SEV-SNP firmware ABI spec
+/// adds from `0x19` (`INVALID_PAGE_SIZE`) through `0x27` (`INVALID_KEY`);
+/// a status of `0x00` means success. Both families of commands share a single
+/// space of status codes, so the SNP codes are never reported by legacy
+/// commands and vice versa.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Something went wrong when communicating with the "outside world"
     /// (kernel, SEV platform).
@@ -102,6 +306,258 @@ pub enum Error {
 
     /// The SEV platform observed a failed integrity check.
     SecureDataInvalid,
+
+    /// The RMP page size is incorrect.
+    InvalidPageSize,
+
+    /// The RMP page state is incorrect.
+    InvalidPageState,
+
+    /// The metadata entry is invalid.
+    InvalidMdataEntry,
+
+    /// The page ownership is incorrect.
+    InvalidPageOwner,
+
+    /// The AEAD algorithm would have overflowed.
+    AeadOverflow,
+
+    /// A mailbox mode command was sent while the SEV firmware was in ring buffer mode.
+    ///
+    /// Ring buffer mode has been exited; the mailbox mode command has not been
+    /// executed.
+    RingBufferExit,
+
+    /// The RMP must be reinitialized.
+    RmpInitRequired,
+
+    /// SVN of the provided image is lower than the committed SVN.
+    BadSvn,
+
+    /// Firmware version anti-rollback.
+    BadVersion,
+
+    /// An invocation of `SNP_SHUTDOWN` is required to complete this action.
+    ShutdownRequired,
+
+    /// The update of the firmware internal state or a guest context page has failed.
+    UpdateFailed,
+
+    /// The installation of the committed firmware image is required.
+    RestoreRequired,
+
+    /// The RMP initialization has failed.
+    RmpInitializationFailed,
+
+    /// The key requested is invalid, not present, or not allowed.
+    InvalidKey,
+
+    /// The firmware reported a status code which is not known to this crate,
+    /// such as one introduced by a newer version of the firmware.
+    Unknown(u32),
+}
+
+/// A broad classification of an [`Error`], for callers which need to decide
+/// how to react to a failure without matching every variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ErrorKind {
+    /// The condition is transient; issuing the command again, possibly after
+    /// a delay or a cache flush, may succeed.
+    Retryable,
+
+    /// The condition is caused by the command's parameters, the state of the
+    /// platform or guest, or the configuration of the host, and will persist
+    /// until the caller changes them.
+    Config,
+
+    /// The condition indicates a failed integrity or hardware check, or is
+    /// not understood; the operation should be abandoned.
+    Fatal,
+}
+
+impl Error {
+    /// Classify this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::IoError(e) => match e.kind() {
+                io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ResourceBusy => ErrorKind::Retryable,
+                io::ErrorKind::InvalidInput
+                | io::ErrorKind::NotFound
+                | io::ErrorKind::PermissionDenied
+                | io::ErrorKind::Unsupported => ErrorKind::Config,
+                _ => ErrorKind::Fatal,
+            },
+
+            Self::HardwarePlatform
+            | Self::ResourceLimit
+            | Self::WbinvdRequired
+            | Self::DfFlushRequired
+            | Self::RingBufferExit => ErrorKind::Retryable,
+
+            Self::InvalidPlatformState
+            | Self::InvalidGuestState
+            | Self::InvalidConfig
+            | Self::InvalidLen
+            | Self::AlreadyOwned
+            | Self::InvalidCertificate
+            | Self::PolicyFailure
+            | Self::Inactive
+            | Self::InvalidAddress
+            | Self::AsidOwned
+            | Self::InvalidAsid
+            | Self::InvalidGuest
+            | Self::InvalidCommand
+            | Self::Active
+            | Self::Unsupported
+            | Self::InvalidParam
+            | Self::InvalidPageSize
+            | Self::InvalidPageState
+            | Self::InvalidMdataEntry
+            | Self::InvalidPageOwner
+            | Self::RmpInitRequired
+            | Self::ShutdownRequired => ErrorKind::Config,
+
+            Self::BadSignature
+            | Self::BadMeasurement
+            | Self::HardwareUnsafe
+            | Self::SecureDataInvalid
+            | Self::AeadOverflow
+            | Self::BadSvn
+            | Self::BadVersion
+            | Self::UpdateFailed
+            | Self::RestoreRequired
+            | Self::RmpInitializationFailed
+            | Self::InvalidKey
+            | Self::Unknown(_) => ErrorKind::Fatal,
+        }
+    }
+
+    /// A suggestion of how to remedy this error, if there is one.
+    pub fn hint(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::IoError(e) => match e.kind() {
+                io::ErrorKind::NotFound => "the device is missing; check that the ccp and kvm_amd modules are loaded",
+                io::ErrorKind::PermissionDenied => "the device is not accessible; check its permissions",
+                _ => return None,
+            },
+            Self::InvalidPlatformState => "the platform must be initialized, or shut down, before issuing this command",
+            Self::InvalidGuestState => "the guest is not in the state this command requires; check the order of launch or migration commands",
+            Self::InvalidLen => "retry with a buffer of the length reported by the firmware",
+            Self::AlreadyOwned => "the platform is already owned; reset it with FACTORY_RESET to take ownership",
+            Self::InvalidCertificate => "the certificate chain is stale or malformed; export it again, or regenerate it with PDH_GEN",
+            Self::PolicyFailure => "the guest policy forbids this command",
+            Self::AsidOwned | Self::InvalidAsid => "the ASID is unavailable; decommission stale guests or adjust the ASID range",
+            Self::WbinvdRequired => "issue WBINVD on every core, then retry",
+            Self::DfFlushRequired => "issue DF_FLUSH, then retry",
+            Self::HardwarePlatform => "retry the command",
+            Self::Unsupported => "the firmware does not support this feature; update the firmware",
+            Self::ResourceLimit => "decommission unused guests, then retry",
+            Self::RmpInitRequired => "the platform requires SNP_INIT",
+            Self::ShutdownRequired => "shut down the platform with SNP_SHUTDOWN, then retry",
+            Self::RestoreRequired => "reinstall the committed firmware image",
+            Self::BadSvn | Self::BadVersion => "the firmware image is older than the committed version; install a newer image",
+            Self::RingBufferExit => "issue the command again in mailbox mode",
+            _ => return None,
+        })
+    }
+
+    /// The status code reported by the firmware for this error, or `None`
+    /// if the error did not originate from the firmware.
+    pub fn code(&self) -> Option<u32> {
+        Some(match self {
+            Self::IoError(_) => return None,
+            Self::InvalidPlatformState => codes::SEV_RET_INVALID_PLATFORM_STATE,
+            Self::InvalidGuestState => codes::SEV_RET_INVALID_GUEST_STATE,
+            Self::InvalidConfig => codes::SEV_RET_INVALID_CONFIG,
+            Self::InvalidLen => codes::SEV_RET_INVALID_LEN,
+            Self::AlreadyOwned => codes::SEV_RET_ALREADY_OWNED,
+            Self::InvalidCertificate => codes::SEV_RET_INVALID_CERTIFICATE,
+            Self::PolicyFailure => codes::SEV_RET_POLICY_FAILURE,
+            Self::Inactive => codes::SEV_RET_INACTIVE,
+            Self::InvalidAddress => codes::SEV_RET_INVALID_ADDRESS,
+            Self::BadSignature => codes::SEV_RET_BAD_SIGNATURE,
+            Self::BadMeasurement => codes::SEV_RET_BAD_MEASUREMENT,
+            Self::AsidOwned => codes::SEV_RET_ASID_OWNED,
+            Self::InvalidAsid => codes::SEV_RET_INVALID_ASID,
+            Self::WbinvdRequired => codes::SEV_RET_WBINVD_REQUIRED,
+            Self::DfFlushRequired => codes::SEV_RET_DFFLUSH_REQUIRED,
+            Self::InvalidGuest => codes::SEV_RET_INVALID_GUEST,
+            Self::InvalidCommand => codes::SEV_RET_INVALID_COMMAND,
+            Self::Active => codes::SEV_RET_ACTIVE,
+            Self::HardwarePlatform => codes::SEV_RET_HWSEV_RET_PLATFORM,
+            Self::HardwareUnsafe => codes::SEV_RET_HWSEV_RET_UNSAFE,
+            Self::Unsupported => codes::SEV_RET_UNSUPPORTED,
+            Self::InvalidParam => codes::SEV_RET_INVALID_PARAM,
+            Self::ResourceLimit => codes::SEV_RET_RESOURCE_LIMIT,
+            Self::SecureDataInvalid => codes::SEV_RET_SECURE_DATA_INVALID,
+            Self::InvalidPageSize => codes::SEV_RET_INVALID_PAGE_SIZE,
+            Self::InvalidPageState => codes::SEV_RET_INVALID_PAGE_STATE,
+            Self::InvalidMdataEntry => codes::SEV_RET_INVALID_MDATA_ENTRY,
+            Self::InvalidPageOwner => codes::SEV_RET_INVALID_PAGE_OWNER,
+            Self::AeadOverflow => codes::SEV_RET_INVALID_PAGE_AEAD_OFLOW,
+            Self::RingBufferExit => codes::SEV_RET_EXIT_RING_BUFFER,
+            Self::RmpInitRequired => codes::SEV_RET_RMP_INIT_REQUIRED,
+            Self::BadSvn => codes::SEV_RET_BAD_SVN,
+            Self::BadVersion => codes::SEV_RET_BAD_VERSION,
+            Self::ShutdownRequired => codes::SEV_RET_SHUTDOWN_REQUIRED,
+            Self::UpdateFailed => codes::SEV_RET_UPDATE_FAILED,
+            Self::RestoreRequired => codes::SEV_RET_RESTORE_REQUIRED,
+            Self::RmpInitializationFailed => codes::SEV_RET_RMP_INITIALIZATION_FAILED,
+            Self::InvalidKey => codes::SEV_RET_INVALID_KEY,
+            Self::Unknown(code) => *code,
+        })
+    }
+
+    /// The name of this error as spelled in the AMD SEV API spec, or `None`
+    /// if the error did not originate from the firmware or is not known.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::IoError(_) => return None,
+            Self::InvalidPlatformState => "INVALID_PLATFORM_STATE",
+            Self::InvalidGuestState => "INVALID_GUEST_STATE",
+            Self::InvalidConfig => "INVALID_CONFIG",
+            Self::InvalidLen => "INVALID_LENGTH",
+            Self::AlreadyOwned => "ALREADY_OWNED",
+            Self::InvalidCertificate => "INVALID_CERTIFICATE",
+            Self::PolicyFailure => "POLICY_FAILURE",
+            Self::Inactive => "INACTIVE",
+            Self::InvalidAddress => "INVALID_ADDRESS",
+            Self::BadSignature => "BAD_SIGNATURE",
+            Self::BadMeasurement => "BAD_MEASUREMENT",
+            Self::AsidOwned => "ASID_OWNED",
+            Self::InvalidAsid => "INVALID_ASID",
+            Self::WbinvdRequired => "WBINVD_REQUIRED",
+            Self::DfFlushRequired => "DFFLUSH_REQUIRED",
+            Self::InvalidGuest => "INVALID_GUEST",
+            Self::InvalidCommand => "INVALID_COMMAND",
+            Self::Active => "ACTIVE",
+            Self::HardwarePlatform => "HWERROR_PLATFORM",
+            Self::HardwareUnsafe => "HWERROR_UNSAFE",
+            Self::Unsupported => "UNSUPPORTED",
+            Self::InvalidParam => "INVALID_PARAM",
+            Self::ResourceLimit => "RESOURCE_LIMIT",
+            Self::SecureDataInvalid => "SECURE_DATA_INVALID",
+            Self::InvalidPageSize => "INVALID_PAGE_SIZE",
+            Self::InvalidPageState => "INVALID_PAGE_STATE",
+            Self::InvalidMdataEntry => "INVALID_MDATA_ENTRY",
+            Self::InvalidPageOwner => "INVALID_PAGE_OWNER",
+            Self::AeadOverflow => "AEAD_OFLOW",
+            Self::RingBufferExit => "RING_BUFFER_EXIT",
+            Self::RmpInitRequired => "RMP_INIT_REQUIRED",
+            Self::BadSvn => "BAD_SVN",
+            Self::BadVersion => "BAD_VERSION",
+            Self::ShutdownRequired => "SHUTDOWN_REQUIRED",
+            Self::UpdateFailed => "UPDATE_FAILED",
+            Self::RestoreRequired => "RESTORE_REQUIRED",
+            Self::RmpInitializationFailed => "RMP_INITIALIZATION_FAILED",
+            Self::InvalidKey => "INVALID_KEY",
+            Self::Unknown(_) => return None,
+        })
+    }
 }
 
 impl AsRef<str> for Error {
@@ -132,13 +588,32 @@ impl AsRef<str> for Error {
             Self::InvalidParam => "Given parameter is invalid",
             Self::ResourceLimit => "SEV firmware is out of required resources",
             Self::SecureDataInvalid => "SEV platform observed a failed integrity check",
+            Self::InvalidPageSize => "RMP page size is incorrect",
+            Self::InvalidPageState => "RMP page state is incorrect",
+            Self::InvalidMdataEntry => "Metadata entry is invalid",
+            Self::InvalidPageOwner => "Page ownership is incorrect",
+            Self::AeadOverflow => "AEAD algorithm would have overflowed",
+            Self::RingBufferExit => "Ring buffer mode exited, command not executed",
+            Self::RmpInitRequired => "RMP must be reinitialized",
+            Self::BadSvn => "Image SVN is lower than the committed SVN",
+            Self::BadVersion => "Firmware version anti-rollback",
+            Self::ShutdownRequired => "SNP_SHUTDOWN invocation required",
+            Self::UpdateFailed => "Update of firmware state or guest context failed",
+            Self::RestoreRequired => "Committed firmware image must be restored",
+            Self::RmpInitializationFailed => "RMP initialization failed",
+            Self::InvalidKey => "Requested key is invalid, missing or not allowed",
+            Self::Unknown(_) => "Unknown firmware error",
         }
     }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.as_ref())
+        match (self.name(), self.code()) {
+            (Some(name), Some(code)) => write!(f, "{} ({:#04x}): {}", name, code, self.as_ref()),
+            (None, Some(code)) => write!(f, "{} ({:#04x})", self.as_ref(), code),
+            _ => write!(f, "{}", self.as_ref()),
+        }
     }
 }
 
@@ -151,6 +626,254 @@ impl error::Error for Error {
     }
 }
 
+/// The failure of a command passed to the firmware, retaining both the error
+/// reported by the kernel and the status reported by the firmware.
+///
+/// Converting a `CommandError` into an `Indeterminate<Error>` keeps only the
+/// firmware error, falling back to the kernel error when the firmware did not
+/// report one.
+#[derive(Debug)]
+pub struct CommandError {
+    os: io::Error,
+    status: u32,
+    command: Option<(u32, Option<&'static str>)>,
+}
+
+impl CommandError {
+    /// Create a new `CommandError` from the error returned by the ioctl and
+    /// the status reported by the firmware.
+    pub fn new(os: io::Error, status: u32) -> Self {
+        Self {
+            os,
+            status,
+            command: None,
+        }
+    }
+
+    /// Record the ID of the command which failed, along with a name for it
+    /// to be used in messages.
+    pub fn with_command(mut self, code: u32, name: Option<&'static str>) -> Self {
+        self.command = Some((code, name));
+        self
+    }
+
+    /// The ID of the command which failed, if known.
+    pub fn command_code(&self) -> Option<u32> {
+        self.command.map(|(code, _)| code)
+    }
+
+    /// The name of the command which failed, if known.
+    pub fn command_name(&self) -> Option<&'static str> {
+        self.command.and_then(|(_, name)| name)
+    }
+
+    /// The error reported by the kernel, such as `EBUSY` or `EIO`.
+    pub fn os_error(&self) -> &io::Error {
+        &self.os
+    }
+
+    /// The status reported by the firmware, where zero means the firmware did
+    /// not report an error.
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    /// The error reported by the firmware, if any.
+    pub fn firmware_error(&self) -> Option<Error> {
+        Error::try_from(self.status).ok()
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.command {
+            Some((code, Some(name))) => write!(f, "{} ({:#x}) failed: ", name, code)?,
+            Some((code, None)) => write!(f, "command {:#x} failed: ", code)?,
+            None => (),
+        }
+
+        match self.firmware_error() {
+            Some(e) => write!(f, "{} (os error: {})", e, self.os),
+            None => write!(f, "{}", self.os),
+        }
+    }
+}
+
+impl error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.os)
+    }
+}
+
+impl From<CommandError> for Indeterminate<Error> {
+    #[inline]
+    fn from(error: CommandError) -> Indeterminate<Error> {
+        Indeterminate::Known(match Error::try_from(error.status) {
+            Ok(e) => e,
+            Err(_) => error.os.into(),
+        })
+    }
+}
+
+impl From<CommandError> for io::Error {
+    #[inline]
+    fn from(error: CommandError) -> io::Error {
+        io::Error::new(error.os.kind(), error)
+    }
+}
+
+/// An error reported by the hypervisor while forwarding a guest request to
+/// the firmware.
+///
+/// These values are defined as `SNP_GUEST_VMM_ERR_*` in the Linux kernel:
+/// include/uapi/linux/sev-guest.h
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum VmmError {
+    /// The certificate buffer of an extended report request is too small.
+    InvalidLen,
+
+    /// The hypervisor is busy, for instance because the guest is being rate
+    /// limited; the request may be retried.
+    Busy,
+
+    /// The hypervisor failed to forward the request for an unspecified
+    /// reason.
+    Generic,
+
+    /// The hypervisor reported an error which is not known to this crate.
+    Unknown(u32),
+}
+
+impl TryFrom<u32> for VmmError {
+    type Error = u32;
+
+    /// Decode an error reported by the hypervisor, which fails only for zero
+    /// since that means the hypervisor did not report an error.
+    fn try_from(error: u32) -> Result<VmmError, u32> {
+        Ok(match error {
+            codes::SEV_RET_SUCCESS => return Err(error),
+            1 => VmmError::InvalidLen,
+            2 => VmmError::Busy,
+            u32::MAX => VmmError::Generic,
+            code => VmmError::Unknown(code),
+        })
+    }
+}
+
+impl std::fmt::Display for VmmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmmError::InvalidLen => write!(f, "Certificate buffer too small"),
+            VmmError::Busy => write!(f, "Hypervisor is busy"),
+            VmmError::Generic => write!(f, "Hypervisor failed to forward the request"),
+            VmmError::Unknown(code) => write!(f, "Unknown hypervisor error ({:#x})", code),
+        }
+    }
+}
+
+/// The failure of a request passed to the firmware from within a guest.
+///
+/// The kernel reports the outcome of a guest request in a single 64-bit
+/// field, with the status reported by the firmware in the low 32 bits and the
+/// error reported by the hypervisor in the high 32 bits. This type decodes
+/// both halves and retains the error reported by the kernel.
+#[derive(Debug)]
+pub struct GuestError {
+    os: io::Error,
+    exitinfo2: u64,
+}
+
+impl GuestError {
+    /// Create a new `GuestError` from the error returned by the ioctl and the
+    /// raw error field of the request.
+    pub fn new(os: io::Error, exitinfo2: u64) -> Self {
+        Self { os, exitinfo2 }
+    }
+
+    /// Create a new `GuestError` for a request which the kernel completed,
+    /// but whose response carries the given non-zero firmware status.
+    pub fn from_status(status: u32) -> Self {
+        let os = io::Error::other(format!("request failed with status {:#x}", status));
+        Self::new(os, status.into())
+    }
+
+    /// The error reported by the kernel.
+    pub fn os_error(&self) -> &io::Error {
+        &self.os
+    }
+
+    /// The status reported by the firmware, where zero means the firmware did
+    /// not report an error.
+    pub fn status(&self) -> u32 {
+        self.exitinfo2 as u32
+    }
+
+    /// The error reported by the firmware, if any.
+    pub fn firmware_error(&self) -> Option<Error> {
+        Error::try_from(self.status()).ok()
+    }
+
+    /// The error reported by the hypervisor, if any.
+    pub fn vmm_error(&self) -> Option<VmmError> {
+        VmmError::try_from((self.exitinfo2 >> 32) as u32).ok()
+    }
+
+    /// Whether the hypervisor refused the request because it is busy, such
+    /// as when it rate limits the guest.
+    pub fn is_throttled(&self) -> bool {
+        self.vmm_error() == Some(VmmError::Busy)
+    }
+
+    /// How long to wait before sending a throttled request again.
+    ///
+    /// The hypervisor does not say how long it is busy for, so this is the
+    /// delay the kernel itself waits between the attempts it makes before
+    /// giving up on a throttled request.
+    pub fn retry_after(&self) -> Option<Duration> {
+        if self.is_throttled() {
+            Some(Duration::from_secs(2))
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for GuestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.firmware_error(), self.vmm_error()) {
+            (Some(fw), Some(vmm)) => write!(f, "{} ({})", fw, vmm),
+            (Some(fw), None) => write!(f, "{}", fw),
+            (None, Some(vmm)) => write!(f, "{}", vmm),
+            (None, None) => write!(f, "{}", self.os),
+        }
+    }
+}
+
+impl error::Error for GuestError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.os)
+    }
+}
+
+impl From<GuestError> for Indeterminate<Error> {
+    #[inline]
+    fn from(error: GuestError) -> Indeterminate<Error> {
+        Indeterminate::Known(match error.firmware_error() {
+            Some(e) => e,
+            None => error.os.into(),
+        })
+    }
+}
+
+impl From<GuestError> for io::Error {
+    #[inline]
+    fn from(error: GuestError) -> io::Error {
+        io::Error::new(error.os.kind(), error)
+    }
+}
+
 impl From<io::Error> for Error {
     #[inline]
     fn from(error: io::Error) -> Error {
@@ -169,42 +892,465 @@ impl From<Indeterminate<Error>> for io::Error {
     #[inline]
     fn from(indeterminate: Indeterminate<Error>) -> io::Error {
         match indeterminate {
-            Indeterminate::Known(e) => io::Error::new(io::ErrorKind::Other, e),
-            Indeterminate::Unknown => io::Error::new(io::ErrorKind::Other, "unknown SEV error"),
+            Indeterminate::Known(e) => io::Error::other(e),
+            Indeterminate::Unknown => io::Error::other("unknown SEV error"),
         }
     }
 }
 
+impl TryFrom<u32> for Error {
+    type Error = u32;
+
+    /// Decode a status code reported by the firmware, which fails only for
+    /// `0x00` since that status means success.
+    #[inline]
+    fn try_from(error: u32) -> Result<Error, u32> {
+        Ok(match error {
+            0 => return Err(error),
+            codes::SEV_RET_INVALID_PLATFORM_STATE => Error::InvalidPlatformState,
+            codes::SEV_RET_INVALID_GUEST_STATE => Error::InvalidGuestState,
+            codes::SEV_RET_INVALID_CONFIG => Error::InvalidConfig,
+            codes::SEV_RET_INVALID_LEN => Error::InvalidLen,
+            codes::SEV_RET_ALREADY_OWNED => Error::AlreadyOwned,
+            codes::SEV_RET_INVALID_CERTIFICATE => Error::InvalidCertificate,
+            codes::SEV_RET_POLICY_FAILURE => Error::PolicyFailure,
+            codes::SEV_RET_INACTIVE => Error::Inactive,
+            codes::SEV_RET_INVALID_ADDRESS => Error::InvalidAddress,
+            codes::SEV_RET_BAD_SIGNATURE => Error::BadSignature,
+            codes::SEV_RET_BAD_MEASUREMENT => Error::BadMeasurement,
+            codes::SEV_RET_ASID_OWNED => Error::AsidOwned,
+            codes::SEV_RET_INVALID_ASID => Error::InvalidAsid,
+            codes::SEV_RET_WBINVD_REQUIRED => Error::WbinvdRequired,
+            codes::SEV_RET_DFFLUSH_REQUIRED => Error::DfFlushRequired,
+            codes::SEV_RET_INVALID_GUEST => Error::InvalidGuest,
+            codes::SEV_RET_INVALID_COMMAND => Error::InvalidCommand,
+            codes::SEV_RET_ACTIVE => Error::Active,
+            codes::SEV_RET_HWSEV_RET_PLATFORM => Error::HardwarePlatform,
+            codes::SEV_RET_HWSEV_RET_UNSAFE => Error::HardwareUnsafe,
+            codes::SEV_RET_UNSUPPORTED => Error::Unsupported,
+            codes::SEV_RET_INVALID_PARAM => Error::InvalidParam,
+            codes::SEV_RET_RESOURCE_LIMIT => Error::ResourceLimit,
+            codes::SEV_RET_SECURE_DATA_INVALID => Error::SecureDataInvalid,
+            codes::SEV_RET_INVALID_PAGE_SIZE => Error::InvalidPageSize,
+            codes::SEV_RET_INVALID_PAGE_STATE => Error::InvalidPageState,
+            codes::SEV_RET_INVALID_MDATA_ENTRY => Error::InvalidMdataEntry,
+            codes::SEV_RET_INVALID_PAGE_OWNER => Error::InvalidPageOwner,
+            codes::SEV_RET_INVALID_PAGE_AEAD_OFLOW => Error::AeadOverflow,
+            codes::SEV_RET_EXIT_RING_BUFFER => Error::RingBufferExit,
+            codes::SEV_RET_RMP_INIT_REQUIRED => Error::RmpInitRequired,
+            codes::SEV_RET_BAD_SVN => Error::BadSvn,
+            codes::SEV_RET_BAD_VERSION => Error::BadVersion,
+            codes::SEV_RET_SHUTDOWN_REQUIRED => Error::ShutdownRequired,
+            codes::SEV_RET_UPDATE_FAILED => Error::UpdateFailed,
+            codes::SEV_RET_RESTORE_REQUIRED => Error::RestoreRequired,
+            codes::SEV_RET_RMP_INITIALIZATION_FAILED => Error::RmpInitializationFailed,
+            codes::SEV_RET_INVALID_KEY => Error::InvalidKey,
+            code => Error::Unknown(code),
+        })
+    }
+}
+
 impl From<u32> for Indeterminate<Error> {
     #[inline]
     fn from(error: u32) -> Indeterminate<Error> {
-        Indeterminate::Known(match error {
-            0 => io::Error::last_os_error().into(),
-            1 => Error::InvalidPlatformState,
-            2 => Error::InvalidGuestState,
-            3 => Error::InvalidConfig,
-            4 => Error::InvalidLen,
-            5 => Error::AlreadyOwned,
-            6 => Error::InvalidCertificate,
-            7 => Error::PolicyFailure,
-            8 => Error::Inactive,
-            9 => Error::InvalidAddress,
-            10 => Error::BadSignature,
-            11 => Error::BadMeasurement,
-            12 => Error::AsidOwned,
-            13 => Error::InvalidAsid,
-            14 => Error::WbinvdRequired,
-            15 => Error::DfFlushRequired,
-            16 => Error::InvalidGuest,
-            17 => Error::InvalidCommand,
-            18 => Error::Active,
-            19 => Error::HardwarePlatform,
-            20 => Error::HardwareUnsafe,
-            21 => Error::Unsupported,
-            22 => Error::InvalidParam,
-            23 => Error::ResourceLimit,
-            24 => Error::SecureDataInvalid,
-            _ => return Indeterminate::Unknown,
+        Indeterminate::Known(match Error::try_from(error) {
+            Ok(e) => e,
+            Err(_) => io::Error::last_os_error().into(),
+        })
+    }
+}
+
+/// A stable numeric representation of an error, for consumers embedding this
+/// crate through an FFI boundary.
+///
+/// Firmware errors keep the status code reported by the firmware, so they are
+/// positive; the remaining conditions are negative.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// The command succeeded.
+    Success = codes::SEV_RET_SUCCESS as i32,
+
+    /// The platform state is invalid for this command.
+    InvalidPlatformState = codes::SEV_RET_INVALID_PLATFORM_STATE as i32,
+
+    /// The guest state is invalid for this command.
+    InvalidGuestState = codes::SEV_RET_INVALID_GUEST_STATE as i32,
+
+    /// The platform configuration is invalid.
+    InvalidConfig = codes::SEV_RET_INVALID_CONFIG as i32,
+
+    /// A memory buffer is too small.
+    InvalidLen = codes::SEV_RET_INVALID_LEN as i32,
+
+    /// The platform is already owned.
+    AlreadyOwned = codes::SEV_RET_ALREADY_OWNED as i32,
+
+    /// The certificate is invalid.
+    InvalidCertificate = codes::SEV_RET_INVALID_CERTIFICATE as i32,
+
+    /// Request is not allowed by guest policy.
+    PolicyFailure = codes::SEV_RET_POLICY_FAILURE as i32,
+
+    /// The guest is inactive.
+    Inactive = codes::SEV_RET_INACTIVE as i32,
+
+    /// The address provided is invalid.
+    InvalidAddress = codes::SEV_RET_INVALID_ADDRESS as i32,
+
+    /// The provided signature is invalid.
+    BadSignature = codes::SEV_RET_BAD_SIGNATURE as i32,
+
+    /// The provided measurement is invalid.
+    BadMeasurement = codes::SEV_RET_BAD_MEASUREMENT as i32,
+
+    /// The ASID is already owned.
+    AsidOwned = codes::SEV_RET_ASID_OWNED as i32,
+
+    /// The ASID is invalid.
+    InvalidAsid = codes::SEV_RET_INVALID_ASID as i32,
+
+    /// WBINVD instruction required.
+    WbinvdRequired = codes::SEV_RET_WBINVD_REQUIRED as i32,
+
+    /// `DF_FLUSH` invocation required.
+    DfFlushRequired = codes::SEV_RET_DFFLUSH_REQUIRED as i32,
+
+    /// The guest handle is invalid.
+    InvalidGuest = codes::SEV_RET_INVALID_GUEST as i32,
+
+    /// The command issued is invalid.
+    InvalidCommand = codes::SEV_RET_INVALID_COMMAND as i32,
+
+    /// The guest is active.
+    Active = codes::SEV_RET_ACTIVE as i32,
+
+    /// A hardware condition has occurred affecting the platform. It is safe
+    /// to re-allocate parameter buffers.
+    HardwarePlatform = codes::SEV_RET_HWSEV_RET_PLATFORM as i32,
+
+    /// A hardware condition has occurred affecting the platform. Re-allocating
+    /// parameter buffers is not safe.
+    HardwareUnsafe = codes::SEV_RET_HWSEV_RET_UNSAFE as i32,
+
+    /// Feature is unsupported.
+    Unsupported = codes::SEV_RET_UNSUPPORTED as i32,
+
+    /// A given parameter is invalid.
+    InvalidParam = codes::SEV_RET_INVALID_PARAM as i32,
+
+    /// The SEV firmware has run out of a resource required to carry out the
+    /// command.
+    ResourceLimit = codes::SEV_RET_RESOURCE_LIMIT as i32,
+
+    /// The SEV platform observed a failed integrity check.
+    SecureDataInvalid = codes::SEV_RET_SECURE_DATA_INVALID as i32,
+
+    /// The RMP page size is incorrect.
+    InvalidPageSize = codes::SEV_RET_INVALID_PAGE_SIZE as i32,
+
+    /// The RMP page state is incorrect.
+    InvalidPageState = codes::SEV_RET_INVALID_PAGE_STATE as i32,
+
+    /// The metadata entry is invalid.
+    InvalidMdataEntry = codes::SEV_RET_INVALID_MDATA_ENTRY as i32,
+
+    /// The page ownership is incorrect.
+    InvalidPageOwner = codes::SEV_RET_INVALID_PAGE_OWNER as i32,
+
+    /// The AEAD algorithm would have overflowed.
+    AeadOverflow = codes::SEV_RET_INVALID_PAGE_AEAD_OFLOW as i32,
+
+    /// A mailbox mode command was sent while the SEV firmware was in ring buffer mode.
+    ///
+    /// Ring buffer mode has been exited; the mailbox mode command has not been
+    /// executed.
+    RingBufferExit = codes::SEV_RET_EXIT_RING_BUFFER as i32,
+
+    /// The RMP must be reinitialized.
+    RmpInitRequired = codes::SEV_RET_RMP_INIT_REQUIRED as i32,
+
+    /// SVN of the provided image is lower than the committed SVN.
+    BadSvn = codes::SEV_RET_BAD_SVN as i32,
+
+    /// Firmware version anti-rollback.
+    BadVersion = codes::SEV_RET_BAD_VERSION as i32,
+
+    /// An invocation of `SNP_SHUTDOWN` is required to complete this action.
+    ShutdownRequired = codes::SEV_RET_SHUTDOWN_REQUIRED as i32,
+
+    /// The update of the firmware internal state or a guest context page has failed.
+    UpdateFailed = codes::SEV_RET_UPDATE_FAILED as i32,
+
+    /// The installation of the committed firmware image is required.
+    RestoreRequired = codes::SEV_RET_RESTORE_REQUIRED as i32,
+
+    /// The RMP initialization has failed.
+    RmpInitializationFailed = codes::SEV_RET_RMP_INITIALIZATION_FAILED as i32,
+
+    /// The key requested is invalid, not present, or not allowed.
+    InvalidKey = codes::SEV_RET_INVALID_KEY as i32,
+
+    /// The kernel reported an error without a firmware status.
+    Os = -1,
+
+    /// The firmware reported a status which is not known to this crate.
+    UnknownFirmware = -2,
+
+    /// The error condition is unknown.
+    Indeterminate = -3,
+}
+
+impl From<&Error> for ErrorCode {
+    fn from(error: &Error) -> ErrorCode {
+        match error {
+            Error::IoError(_) => ErrorCode::Os,
+            Error::InvalidPlatformState => ErrorCode::InvalidPlatformState,
+            Error::InvalidGuestState => ErrorCode::InvalidGuestState,
+            Error::InvalidConfig => ErrorCode::InvalidConfig,
+            Error::InvalidLen => ErrorCode::InvalidLen,
+            Error::AlreadyOwned => ErrorCode::AlreadyOwned,
+            Error::InvalidCertificate => ErrorCode::InvalidCertificate,
+            Error::PolicyFailure => ErrorCode::PolicyFailure,
+            Error::Inactive => ErrorCode::Inactive,
+            Error::InvalidAddress => ErrorCode::InvalidAddress,
+            Error::BadSignature => ErrorCode::BadSignature,
+            Error::BadMeasurement => ErrorCode::BadMeasurement,
+            Error::AsidOwned => ErrorCode::AsidOwned,
+            Error::InvalidAsid => ErrorCode::InvalidAsid,
+            Error::WbinvdRequired => ErrorCode::WbinvdRequired,
+            Error::DfFlushRequired => ErrorCode::DfFlushRequired,
+            Error::InvalidGuest => ErrorCode::InvalidGuest,
+            Error::InvalidCommand => ErrorCode::InvalidCommand,
+            Error::Active => ErrorCode::Active,
+            Error::HardwarePlatform => ErrorCode::HardwarePlatform,
+            Error::HardwareUnsafe => ErrorCode::HardwareUnsafe,
+            Error::Unsupported => ErrorCode::Unsupported,
+            Error::InvalidParam => ErrorCode::InvalidParam,
+            Error::ResourceLimit => ErrorCode::ResourceLimit,
+            Error::SecureDataInvalid => ErrorCode::SecureDataInvalid,
+            Error::InvalidPageSize => ErrorCode::InvalidPageSize,
+            Error::InvalidPageState => ErrorCode::InvalidPageState,
+            Error::InvalidMdataEntry => ErrorCode::InvalidMdataEntry,
+            Error::InvalidPageOwner => ErrorCode::InvalidPageOwner,
+            Error::AeadOverflow => ErrorCode::AeadOverflow,
+            Error::RingBufferExit => ErrorCode::RingBufferExit,
+            Error::RmpInitRequired => ErrorCode::RmpInitRequired,
+            Error::BadSvn => ErrorCode::BadSvn,
+            Error::BadVersion => ErrorCode::BadVersion,
+            Error::ShutdownRequired => ErrorCode::ShutdownRequired,
+            Error::UpdateFailed => ErrorCode::UpdateFailed,
+            Error::RestoreRequired => ErrorCode::RestoreRequired,
+            Error::RmpInitializationFailed => ErrorCode::RmpInitializationFailed,
+            Error::InvalidKey => ErrorCode::InvalidKey,
+            Error::Unknown(_) => ErrorCode::UnknownFirmware,
+        }
+    }
+}
+
+impl From<&Indeterminate<Error>> for ErrorCode {
+    fn from(error: &Indeterminate<Error>) -> ErrorCode {
+        match error {
+            Indeterminate::Known(e) => e.into(),
+            Indeterminate::Unknown => ErrorCode::Indeterminate,
+        }
+    }
+}
+
+/// Translate a status reported by the firmware into an [`ErrorCode`],
+/// returned as its `i32` value.
+///
+/// This crate is only built as an `rlib`, so the function is not exported to
+/// C by itself. A C consumer links against a `staticlib` or `cdylib` crate
+/// which wraps it in a `#[no_mangle]` function and declares that function in
+/// its own header.
+pub extern "C" fn sev_iocuddle_error_code(status: u32) -> i32 {
+    let code = match Error::try_from(status) {
+        Ok(e) => ErrorCode::from(&e),
+        Err(_) => ErrorCode::Success,
+    };
+
+    code as i32
+}
+
+/// A buffer rejected before it was handed to the kernel, because the command
+/// it was meant for could not describe or accept it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InputError {
+    /// The buffer is longer than its length field can describe.
+    TooLong {
+        /// The length of the buffer.
+        len: usize,
+
+        /// The maximum length the command accepts.
+        max: usize,
+    },
+
+    /// The buffer does not start at the alignment the command requires.
+    Misaligned {
+        /// The address of the buffer.
+        addr: usize,
+
+        /// The alignment the command requires.
+        align: usize,
+    },
+
+    /// The length of the buffer is not a multiple of the granularity the
+    /// command requires.
+    BadLength {
+        /// The length of the buffer.
+        len: usize,
+
+        /// The granularity the command requires.
+        multiple: usize,
+    },
+
+    /// The VMPL is above the least privileged level, VMPL3.
+    InvalidVmpl(u32),
+
+    /// Two buffers which the command transfers between differ in length.
+    LengthMismatch {
+        /// The length of the source buffer.
+        src: usize,
+
+        /// The length of the destination buffer.
+        dst: usize,
+    },
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputError::TooLong { len, max } => {
+                write!(f, "Buffer of {} bytes exceeds the maximum of {}", len, max)
+            }
+            InputError::Misaligned { addr, align } => {
+                write!(f, "Buffer at {:#x} is not aligned to {} bytes", addr, align)
+            }
+            InputError::BadLength { len, multiple } => {
+                write!(
+                    f,
+                    "Buffer of {} bytes is not a multiple of {}",
+                    len, multiple
+                )
+            }
+            InputError::InvalidVmpl(vmpl) => write!(f, "VMPL {} is not between 0 and 3", vmpl),
+            InputError::LengthMismatch { src, dst } => write!(
+                f,
+                "Source of {} bytes does not match destination of {} bytes",
+                src, dst
+            ),
+        }
+    }
+}
+
+impl error::Error for InputError {}
+
+impl From<InputError> for io::Error {
+    #[inline]
+    fn from(error: InputError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+impl From<InputError> for Indeterminate<Error> {
+    #[inline]
+    fn from(error: InputError) -> Indeterminate<Error> {
+        Indeterminate::Known(Error::IoError(error.into()))
+    }
+}
+
+impl From<InputError> for GuestError {
+    #[inline]
+    fn from(error: InputError) -> GuestError {
+        GuestError::new(error.into(), 0)
+    }
+}
+
+/// The stable wire representation of the errors in this module.
+///
+/// Firmware errors are identified by their status code and kernel errors by
+/// their errno, while the message is informational only.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ErrorRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    os_error: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exitinfo2: Option<u64>,
+
+    message: String,
+}
+
+#[cfg(feature = "serde")]
+impl ErrorRepr {
+    fn os_error(&self) -> io::Error {
+        match self.os_error {
+            Some(errno) => io::Error::from_raw_os_error(errno),
+            None => io::Error::other(self.message.clone()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorRepr {
+            code: self.code(),
+            name: self.name().map(String::from),
+            os_error: match self {
+                Self::IoError(e) => e.raw_os_error(),
+                _ => None,
+            },
+            exitinfo2: None,
+            message: self.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Error {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ErrorRepr::deserialize(deserializer)?;
+        Ok(match repr.code.map(Error::try_from) {
+            Some(Ok(e)) => e,
+            _ => Error::IoError(repr.os_error()),
         })
     }
 }
+
+#[cfg(feature = "serde")]
+impl Serialize for GuestError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorRepr {
+            code: self.firmware_error().and_then(|e| e.code()),
+            name: self
+                .firmware_error()
+                .and_then(|e| e.name())
+                .map(String::from),
+            os_error: self.os.raw_os_error(),
+            exitinfo2: Some(self.exitinfo2),
+            message: self.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for GuestError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ErrorRepr::deserialize(deserializer)?;
+        let exitinfo2 = repr.exitinfo2.or(repr.code.map(u64::from)).unwrap_or(0);
+        Ok(GuestError::new(repr.os_error(), exitinfo2))
+    }
+}
@@ -0,0 +1,835 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// Helpful abstractions for issuing ioctls to the SEV-SNP guest device (`/dev/sev-guest`).
+use crate::assert_layout;
+use crate::device::{DeviceOptions, SevDevice};
+use crate::error::{Error, GuestError, Indeterminate, InputError, VmmError};
+use crate::sev::TcbVersion;
+use crate::util::{try_len_u32, AlignedBuffer, ByteArray, OutBuf, Sensitive, UserPtr};
+
+use bitflags::bitflags;
+use iocuddle::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind};
+use std::marker::PhantomData;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+
+/// The SEV guest iocuddle group.
+pub const SEV_GUEST: Group = Group::new(b'S');
+
+/// Corresponds to the `SNP_GET_REPORT` ioctl
+pub const GET_REPORT: Ioctl<WriteRead, &GuestRequest<ReportReq, ReportResp>> =
+    unsafe { SEV_GUEST.write_read(0x0) };
+
+/// Corresponds to the `SNP_GET_DERIVED_KEY` ioctl
+pub const GET_DERIVED_KEY: Ioctl<WriteRead, &GuestRequest<DerivedKeyReq, DerivedKeyResp>> =
+    unsafe { SEV_GUEST.write_read(0x1) };
+
+/// Corresponds to the `SNP_GET_EXT_REPORT` ioctl
+pub const GET_EXT_REPORT: Ioctl<WriteRead, &GuestRequest<ExtReportReq, ReportResp>> =
+    unsafe { SEV_GUEST.write_read(0x2) };
+
+/// The granularity of the certificate buffer of an extended report request.
+const PAGE_SIZE: usize = 4096;
+
+/// The Rust-flavored, FFI-friendly version of `struct snp_guest_request_ioctl`
+/// which is used to pass requests to the SEV-SNP firmware from within a guest.
+///
+/// This struct is defined in the Linux kernel: include/uapi/linux/sev-guest.h
+#[repr(C)]
+pub struct GuestRequest<'a, Req, Resp> {
+    msg_version: u8,
+    req_data: UserPtr<Req>,
+    resp_data: UserPtr<Resp>,
+    exitinfo2: u64,
+    phantom: PhantomData<(&'a Req, &'a Resp)>,
+}
+
+assert_layout!(GuestRequest<'static, ReportReq, ReportResp>, 32, 8);
+
+impl<'a, Req, Resp> GuestRequest<'a, Req, Resp> {
+    /// Create a new guest request using the given message version, where the
+    /// kernel may also write to the request (or a region it references) in
+    /// addition to writing the firmware's response into `resp`.
+    pub fn from_mut(msg_version: u8, req: &'a mut Req, resp: &'a mut Resp) -> Self {
+        Self {
+            msg_version,
+            req_data: UserPtr::from_mut(req),
+            resp_data: UserPtr::from_mut(resp),
+            exitinfo2: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Create a new guest request using the given message version, where the
+    /// firmware writes its response into `resp`.
+    pub fn new(msg_version: u8, req: &'a Req, resp: &'a mut Resp) -> Self {
+        Self {
+            msg_version,
+            req_data: UserPtr::from_ref(req),
+            resp_data: UserPtr::from_mut(resp),
+            exitinfo2: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Create a new guest request like [`GuestRequest::from_mut`], where the
+    /// response is written into uninitialized storage.
+    pub fn from_mut_uninit(msg_version: u8, req: &'a mut Req, resp: &'a mut OutBuf<Resp>) -> Self {
+        Self {
+            msg_version,
+            req_data: UserPtr::from_mut(req),
+            resp_data: UserPtr::from_ptr(resp.as_mut_ptr()),
+            exitinfo2: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Create a new guest request like [`GuestRequest::new`], where the
+    /// response is written into uninitialized storage.
+    pub fn new_uninit(msg_version: u8, req: &'a Req, resp: &'a mut OutBuf<Resp>) -> Self {
+        Self {
+            msg_version,
+            req_data: UserPtr::from_ref(req),
+            resp_data: UserPtr::from_ptr(resp.as_mut_ptr()),
+            exitinfo2: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// The error reported by the hypervisor, as opposed to the firmware.
+    pub fn vmm_error(&self) -> u32 {
+        (self.exitinfo2 >> 32) as _
+    }
+
+    /// Pair the error returned by the ioctl with the errors reported by the
+    /// firmware and the hypervisor, so that none of them is lost.
+    pub fn error(&self, err: std::io::Error) -> GuestError {
+        GuestError::new(err, self.exitinfo2)
+    }
+
+    /// Rather than relying on status codes from the Linux kernel, match the specific error code
+    /// returned by the SNP firmware to output errors in more detail.
+    pub fn encapsulate(&self, err: std::io::Error) -> Indeterminate<Error> {
+        self.error(err).into()
+    }
+}
+
+/// A Virtual Machine Privilege Level, from VMPL0, the most privileged, to
+/// VMPL3.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Vmpl(u8);
+
+impl Vmpl {
+    /// The most privileged level, at which requests are made by default.
+    pub const VMPL0: Vmpl = Vmpl(0);
+
+    /// The second most privileged level.
+    pub const VMPL1: Vmpl = Vmpl(1);
+
+    /// The second least privileged level.
+    pub const VMPL2: Vmpl = Vmpl(2);
+
+    /// The least privileged level.
+    pub const VMPL3: Vmpl = Vmpl(3);
+
+    /// Validate a raw VMPL.
+    pub fn new(vmpl: u32) -> Result<Self, InputError> {
+        match vmpl {
+            0..=3 => Ok(Vmpl(vmpl as _)),
+            _ => Err(InputError::InvalidVmpl(vmpl)),
+        }
+    }
+
+    /// The raw VMPL.
+    pub fn get(self) -> u32 {
+        self.0 as _
+    }
+}
+
+impl TryFrom<u32> for Vmpl {
+    type Error = InputError;
+
+    fn try_from(vmpl: u32) -> Result<Self, InputError> {
+        Self::new(vmpl)
+    }
+}
+
+impl From<Vmpl> for u32 {
+    fn from(vmpl: Vmpl) -> u32 {
+        vmpl.get()
+    }
+}
+
+impl std::fmt::Display for Vmpl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VMPL{}", self.0)
+    }
+}
+
+/// The 64 bytes of data a guest includes in an attestation report, usually
+/// to bind the report to a key or a nonce.
+///
+/// Data shorter than 64 bytes, such as a digest, is padded with zeros.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReportData(ByteArray<64>);
+
+impl ReportData {
+    /// The size of the report data.
+    pub const SIZE: usize = 64;
+
+    /// Use `data` as the report data.
+    pub fn new(data: [u8; 64]) -> Self {
+        Self(ByteArray(data))
+    }
+
+    /// Use `data` as the report data, padded with zeros, failing if it is
+    /// longer than 64 bytes.
+    pub fn from_slice(data: &[u8]) -> Result<Self, InputError> {
+        if data.len() > Self::SIZE {
+            return Err(InputError::TooLong {
+                len: data.len(),
+                max: Self::SIZE,
+            });
+        }
+
+        let mut padded = [0; 64];
+        padded[..data.len()].copy_from_slice(data);
+        Ok(Self::new(padded))
+    }
+
+    /// Use the SHA-384 digest of `data`, padded with zeros, as the report data.
+    #[cfg(feature = "sha2")]
+    pub fn sha384(data: impl AsRef<[u8]>) -> Self {
+        use sha2::{Digest, Sha384};
+
+        let digest = Sha384::digest(data.as_ref());
+        Self::from_slice(&digest).unwrap()
+    }
+
+    /// Use the SHA-512 digest of `data` as the report data.
+    #[cfg(feature = "sha2")]
+    pub fn sha512(data: impl AsRef<[u8]>) -> Self {
+        use sha2::{Digest, Sha512};
+
+        let digest = Sha512::digest(data.as_ref());
+        Self::from_slice(&digest).unwrap()
+    }
+
+    /// The bytes of the report data.
+    pub fn as_bytes(&self) -> &[u8; 64] {
+        &self.0
+    }
+}
+
+impl From<[u8; 64]> for ReportData {
+    fn from(data: [u8; 64]) -> Self {
+        Self::new(data)
+    }
+}
+
+impl From<ByteArray<64>> for ReportData {
+    fn from(data: ByteArray<64>) -> Self {
+        Self(data)
+    }
+}
+
+impl From<ReportData> for [u8; 64] {
+    fn from(data: ReportData) -> Self {
+        data.0.into()
+    }
+}
+
+impl PartialEq<ByteArray<64>> for ReportData {
+    /// Compare with the report data of an attestation report.
+    fn eq(&self, other: &ByteArray<64>) -> bool {
+        self.0 == *other
+    }
+}
+
+impl AsRef<[u8]> for ReportData {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl std::fmt::Display for ReportData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A request for an attestation report.
+///
+/// Corresponds to the kernel struct `snp_report_req`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReportReq {
+    user_data: [u8; 64],
+    vmpl: u32,
+    rsvd: [u8; 28],
+}
+
+assert_layout!(ReportReq, 96, 4);
+
+impl ReportReq {
+    /// Create a new `ReportReq` for a report containing `user_data` and
+    /// reflecting the given VMPL.
+    pub fn new(user_data: impl Into<ReportData>, vmpl: Vmpl) -> Self {
+        Self {
+            user_data: user_data.into().into(),
+            vmpl: vmpl.get(),
+            rsvd: [0; 28],
+        }
+    }
+
+    /// The data to include in the report.
+    pub fn user_data(&self) -> &[u8; 64] {
+        &self.user_data
+    }
+
+    /// The VMPL the report reflects.
+    pub fn vmpl(&self) -> Vmpl {
+        Vmpl(self.vmpl as _)
+    }
+
+    /// Request a report reflecting the given VMPL instead.
+    pub fn with_vmpl(mut self, vmpl: Vmpl) -> Self {
+        self.vmpl = vmpl.get();
+        self
+    }
+}
+
+impl Default for ReportReq {
+    /// A request for a report at VMPL0 containing no user data.
+    fn default() -> Self {
+        Self::new(ReportData::default(), Vmpl::VMPL0)
+    }
+}
+
+/// The response to a request for an attestation report.
+///
+/// Corresponds to the kernel struct `snp_report_resp`, whose contents are
+/// the `MSG_REPORT_RSP` message of the SEV-SNP firmware ABI.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReportResp {
+    data: [u8; 4000],
+}
+
+assert_layout!(ReportResp, 4000, 1);
+
+impl ReportResp {
+    /// The status of the request, as reported by the firmware.
+    ///
+    /// The kernel completes a request even when the firmware fails it, so
+    /// the rest of the response is only meaningful if this is zero.
+    pub fn status(&self) -> u32 {
+        u32::from_le_bytes([self.data[0], self.data[1], self.data[2], self.data[3]])
+    }
+
+    /// The size of the attestation report in bytes.
+    pub fn report_size(&self) -> usize {
+        u32::from_le_bytes([self.data[4], self.data[5], self.data[6], self.data[7]]) as _
+    }
+
+    /// The raw attestation report.
+    pub fn report(&self) -> &[u8] {
+        let end = 32usize
+            .saturating_add(self.report_size())
+            .min(self.data.len());
+        &self.data[32..end]
+    }
+}
+
+impl Default for ReportResp {
+    fn default() -> Self {
+        Self { data: [0; 4000] }
+    }
+}
+
+/// A request for an attestation report together with the certificates needed
+/// to verify it, as provided by the hypervisor.
+///
+/// Corresponds to the kernel struct `snp_ext_report_req`. The length of the
+/// certificate buffer must be a multiple of the page size; when it is too
+/// small, the request fails with a VMM error and the required length is
+/// written back. [`ExtReportReq::fetch`] takes care of sizing the buffer.
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct ExtReportReq<'a> {
+    data: ReportReq,
+    certs_address: UserPtr<[u8]>,
+    certs_len: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(ExtReportReq<'static>, 112, 8);
+
+impl<'a> ExtReportReq<'a> {
+    /// Create a new `ExtReportReq` referencing the buffer which receives the
+    /// certificate table, whose length must be a multiple of the page size
+    /// (see [`AlignedBuffer`]).
+    pub fn new(data: ReportReq, certs: &'a mut [u8]) -> Result<Self, InputError> {
+        if certs.len() % PAGE_SIZE != 0 {
+            return Err(InputError::BadLength {
+                len: certs.len(),
+                multiple: PAGE_SIZE,
+            });
+        }
+
+        Ok(Self {
+            data,
+            certs_address: UserPtr::from_mut(certs),
+            certs_len: try_len_u32(certs)?,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the certificate buffer, as reported by the kernel.
+    pub fn certs_length(&self) -> usize {
+        self.certs_len as _
+    }
+}
+
+impl ExtReportReq<'_> {
+    /// Retrieve an attestation report and the raw certificate table, growing
+    /// the certificate buffer once if the kernel reports it is too small.
+    pub fn fetch(guest: &impl AsFd, data: ReportReq) -> Result<(ReportResp, Vec<u8>), GuestError> {
+        Self::fetch_with_version(guest, 1, data)
+    }
+
+    fn fetch_with_version(
+        guest: &impl AsFd,
+        msg_version: u8,
+        data: ReportReq,
+    ) -> Result<(ReportResp, Vec<u8>), GuestError> {
+        let mut certs = AlignedBuffer::new(PAGE_SIZE);
+
+        loop {
+            let mut resp = OutBuf::new();
+            let mut req = ExtReportReq::new(data, &mut certs)?;
+            let mut request = GuestRequest::from_mut_uninit(msg_version, &mut req, &mut resp);
+
+            let err = match GET_EXT_REPORT.ioctl(&mut guest.as_fd(), &mut request) {
+                // The kernel writes the whole response once the request succeeds.
+                Ok(_) => return Ok((unsafe { resp.assume_init() }, certs.to_vec())),
+                Err(e) => e,
+            };
+
+            let err = request.error(err);
+            let retry = err.vmm_error() == Some(VmmError::InvalidLen);
+            let len = req.certs_length();
+            if !retry || len <= certs.len() {
+                return Err(err);
+            }
+
+            certs = AlignedBuffer::new(len);
+        }
+    }
+}
+
+/// The root key from which a key is derived.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RootKey {
+    /// The versioned chip endorsement key.
+    Vcek = 0,
+
+    /// The VM root key, provided by the migration agent.
+    Vmrk = 1,
+}
+
+bitflags! {
+    /// The guest data which is mixed into a derived key.
+    #[derive(Default)]
+    pub struct GuestFieldSelect: u64 {
+        /// Mix in the guest policy.
+        const GUEST_POLICY = 1 << 0;
+
+        /// Mix in the image ID provided at launch.
+        const IMAGE_ID = 1 << 1;
+
+        /// Mix in the family ID provided at launch.
+        const FAMILY_ID = 1 << 2;
+
+        /// Mix in the launch measurement.
+        const MEASUREMENT = 1 << 3;
+
+        /// Mix in the guest SVN provided at launch.
+        const GUEST_SVN = 1 << 4;
+
+        /// Mix in the TCB version.
+        const TCB_VERSION = 1 << 5;
+    }
+}
+
+/// A request for a key derived from a root key.
+///
+/// Corresponds to the kernel struct `snp_derived_key_req`. The default request
+/// derives a key from the VCEK at VMPL0 without mixing in any guest data; the
+/// `with_*` and [`DerivedKeyReq::mix_in`] methods build other requests from it.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DerivedKeyReq {
+    root_key_select: u32,
+    rsvd: u32,
+    guest_field_select: u64,
+    vmpl: u32,
+    guest_svn: u32,
+    tcb_version: u64,
+}
+
+assert_layout!(DerivedKeyReq, 32, 8);
+
+impl DerivedKeyReq {
+    /// Create a new `DerivedKeyReq` for a key derived from `root_key`, mixing
+    /// in the selected guest fields. The VMPL, guest SVN and TCB version must
+    /// not exceed the guest's own values.
+    pub fn new(
+        root_key: RootKey,
+        guest_field_select: GuestFieldSelect,
+        vmpl: Vmpl,
+        guest_svn: u32,
+        tcb_version: u64,
+    ) -> Self {
+        Self {
+            root_key_select: root_key as _,
+            rsvd: 0,
+            guest_field_select: guest_field_select.bits(),
+            vmpl: vmpl.get(),
+            guest_svn,
+            tcb_version,
+        }
+    }
+
+    /// The root key the key is derived from.
+    pub fn root_key(&self) -> RootKey {
+        match self.root_key_select {
+            1 => RootKey::Vmrk,
+            _ => RootKey::Vcek,
+        }
+    }
+
+    /// Derive the key from the given root key instead.
+    pub fn with_root_key(mut self, root_key: RootKey) -> Self {
+        self.root_key_select = root_key as _;
+        self
+    }
+
+    /// The guest data mixed into the key.
+    pub fn guest_field_select(&self) -> GuestFieldSelect {
+        GuestFieldSelect::from_bits_truncate(self.guest_field_select)
+    }
+
+    /// Mix the given guest data into the key as well.
+    ///
+    /// The guest SVN and TCB version are better mixed in through
+    /// [`DerivedKeyReq::with_guest_svn`] and
+    /// [`DerivedKeyReq::with_tcb_version`], which also set their values.
+    pub fn mix_in(mut self, fields: GuestFieldSelect) -> Self {
+        self.guest_field_select |= fields.bits();
+        self
+    }
+
+    /// The guest SVN mixed into the key.
+    pub fn guest_svn(&self) -> u32 {
+        self.guest_svn
+    }
+
+    /// Mix the given guest SVN, which must not exceed that of the guest, into
+    /// the key.
+    pub fn with_guest_svn(mut self, guest_svn: u32) -> Self {
+        self.guest_svn = guest_svn;
+        self.mix_in(GuestFieldSelect::GUEST_SVN)
+    }
+
+    /// The TCB version mixed into the key.
+    pub fn tcb_version(&self) -> TcbVersion {
+        self.tcb_version.into()
+    }
+
+    /// Mix the given TCB version, which must not exceed the committed TCB
+    /// version of the platform, into the key.
+    pub fn with_tcb_version(mut self, tcb_version: TcbVersion) -> Self {
+        self.tcb_version = tcb_version.into();
+        self.mix_in(GuestFieldSelect::TCB_VERSION)
+    }
+
+    /// The VMPL mixed into the key.
+    pub fn vmpl(&self) -> Vmpl {
+        Vmpl(self.vmpl as _)
+    }
+
+    /// Derive the key for the given VMPL instead.
+    pub fn with_vmpl(mut self, vmpl: Vmpl) -> Self {
+        self.vmpl = vmpl.get();
+        self
+    }
+}
+
+/// The response to a request for a derived key.
+///
+/// Corresponds to the kernel struct `snp_derived_key_resp`, whose contents
+/// are the `MSG_KEY_RSP` message of the SEV-SNP firmware ABI.
+///
+/// The response is wiped when dropped, and it can be neither copied nor
+/// cloned, so the key it holds is never left behind in memory.
+#[repr(C)]
+#[derive(PartialEq, Eq)]
+pub struct DerivedKeyResp {
+    data: [u8; 64],
+}
+
+assert_layout!(DerivedKeyResp, 64, 1);
+
+impl DerivedKeyResp {
+    /// The status of the request, as reported by the firmware.
+    ///
+    /// The kernel completes a request even when the firmware fails it, so
+    /// the rest of the response is only meaningful if this is zero.
+    pub fn status(&self) -> u32 {
+        u32::from_le_bytes([self.data[0], self.data[1], self.data[2], self.data[3]])
+    }
+
+    /// The derived key.
+    pub fn key(&self) -> &[u8] {
+        &self.data[32..]
+    }
+
+    /// Take the derived key, wiping the response in place.
+    pub fn take_key(&mut self) -> Sensitive<[u8; 32]> {
+        let mut key = Sensitive::new([0u8; 32]);
+        key.copy_from_slice(&self.data[32..]);
+        drop(Sensitive::new(&mut self.data[..]));
+        key
+    }
+
+    /// Take the derived key, wiping the response.
+    pub fn into_key(mut self) -> Sensitive<[u8; 32]> {
+        self.take_key()
+    }
+}
+
+impl std::fmt::Debug for DerivedKeyResp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DerivedKeyResp")
+            .field("status", &self.status())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for DerivedKeyResp {
+    fn drop(&mut self) {
+        drop(Sensitive::new(&mut self.data[..]));
+    }
+}
+
+impl Default for DerivedKeyResp {
+    fn default() -> Self {
+        Self { data: [0; 64] }
+    }
+}
+
+/// Fail a request whose response carries a non-zero firmware status.
+fn check_status(status: u32) -> Result<(), GuestError> {
+    match status {
+        0 => Ok(()),
+        status => Err(GuestError::from_status(status)),
+    }
+}
+
+/// The SEV-SNP guest device, reached through `/dev/sev-guest` from within a
+/// guest.
+///
+/// Requests are sent with the highest message version known to be accepted,
+/// starting from [`SevGuest::MSG_VERSION`]: whenever the kernel rejects the
+/// version outright, the request is retried with the previous version, which
+/// is then used for every following request.
+#[derive(Debug)]
+pub struct SevGuest {
+    fd: OwnedFd,
+    msg_version: u8,
+}
+
+impl SevGuest {
+    /// The message version requests are first sent with.
+    pub const MSG_VERSION: u8 = 1;
+
+    /// Open the SEV-SNP guest device.
+    pub fn open() -> io::Result<SevGuest> {
+        Self::open_with(&DeviceOptions::new())
+    }
+
+    /// Open the SEV-SNP guest device with the given options, such as another
+    /// path than `/dev/sev-guest`.
+    pub fn open_with(options: &DeviceOptions) -> io::Result<SevGuest> {
+        let file = options.open("/dev/sev-guest")?;
+        Ok(OwnedFd::from(file).into())
+    }
+
+    /// The message version requests are sent with.
+    pub fn msg_version(&self) -> u8 {
+        self.msg_version
+    }
+
+    /// Send the following requests with the given message version, such as a
+    /// newer one than [`SevGuest::MSG_VERSION`] which the firmware supports.
+    pub fn set_msg_version(&mut self, msg_version: u8) {
+        self.msg_version = msg_version.max(1);
+    }
+
+    /// Whether a request failed because the kernel rejected its message
+    /// version, in which case an older version may be tried.
+    fn downgrade(&mut self, err: &GuestError) -> bool {
+        let rejected = err.status() == 0
+            && err.vmm_error().is_none()
+            && err.os_error().raw_os_error().is_some()
+            && err.os_error().kind() == ErrorKind::InvalidInput;
+
+        if rejected && self.msg_version > 1 {
+            self.msg_version -= 1;
+            return true;
+        }
+
+        false
+    }
+
+    /// Issue a guest request, retrying with older message versions while the
+    /// kernel rejects them.
+    fn request<Req: 'static, Resp: 'static>(
+        &mut self,
+        ioctl: impl Fn() -> Ioctl<WriteRead, &'static GuestRequest<'static, Req, Resp>>,
+        req: &Req,
+        resp: &mut Resp,
+    ) -> Result<(), GuestError> {
+        loop {
+            let mut request = GuestRequest::new(self.msg_version, req, resp);
+            let err = match ioctl().ioctl(&mut self.fd, &mut request) {
+                Ok(_) => return Ok(()),
+                Err(e) => request.error(e),
+            };
+
+            if !self.downgrade(&err) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Request an attestation report.
+    ///
+    /// A non-zero [`ReportResp::status`] is returned as an error, so the
+    /// returned response always holds a report.
+    pub fn get_report(&mut self, req: &ReportReq) -> Result<ReportResp, GuestError> {
+        let mut resp = ReportResp::default();
+        self.request(|| GET_REPORT, req, &mut resp)?;
+        check_status(resp.status())?;
+        Ok(resp)
+    }
+
+    /// Request a key derived from a root key.
+    ///
+    /// A non-zero [`DerivedKeyResp::status`] is returned as an error.
+    pub fn get_derived_key(
+        &mut self,
+        req: &DerivedKeyReq,
+    ) -> Result<Sensitive<[u8; 32]>, GuestError> {
+        let mut resp = DerivedKeyResp::default();
+        self.request(|| GET_DERIVED_KEY, req, &mut resp)?;
+        check_status(resp.status())?;
+        Ok(resp.take_key())
+    }
+
+    /// Request an attestation report along with the raw certificate table
+    /// provided by the host.
+    ///
+    /// A non-zero [`ReportResp::status`] is returned as an error.
+    pub fn get_ext_report(&mut self, req: ReportReq) -> Result<(ReportResp, Vec<u8>), GuestError> {
+        loop {
+            match ExtReportReq::fetch_with_version(&self.fd, self.msg_version, req) {
+                Err(err) if self.downgrade(&err) => continue,
+                Err(err) => return Err(err),
+                Ok((resp, certs)) => {
+                    check_status(resp.status())?;
+                    return Ok((resp, certs));
+                }
+            }
+        }
+    }
+}
+
+impl SevDevice<GuestRequest<'_, ReportReq, ReportResp>> for SevGuest {
+    type Error = GuestError;
+
+    fn issue_ioctl(
+        &mut self,
+        request: &mut GuestRequest<'_, ReportReq, ReportResp>,
+    ) -> Result<(), GuestError> {
+        GET_REPORT
+            .ioctl(&mut self.fd, request)
+            .map_err(|e| request.error(e))?;
+        Ok(())
+    }
+}
+
+impl SevDevice<GuestRequest<'_, DerivedKeyReq, DerivedKeyResp>> for SevGuest {
+    type Error = GuestError;
+
+    fn issue_ioctl(
+        &mut self,
+        request: &mut GuestRequest<'_, DerivedKeyReq, DerivedKeyResp>,
+    ) -> Result<(), GuestError> {
+        GET_DERIVED_KEY
+            .ioctl(&mut self.fd, request)
+            .map_err(|e| request.error(e))?;
+        Ok(())
+    }
+}
+
+impl SevDevice<GuestRequest<'_, ExtReportReq<'_>, ReportResp>> for SevGuest {
+    type Error = GuestError;
+
+    fn issue_ioctl(
+        &mut self,
+        request: &mut GuestRequest<'_, ExtReportReq<'_>, ReportResp>,
+    ) -> Result<(), GuestError> {
+        GET_EXT_REPORT
+            .ioctl(&mut self.fd, request)
+            .map_err(|e| request.error(e))?;
+        Ok(())
+    }
+}
+
+impl AsFd for SevGuest {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for SevGuest {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<OwnedFd> for SevGuest {
+    /// Use an already open `/dev/sev-guest` file descriptor, sending requests
+    /// starting from [`SevGuest::MSG_VERSION`].
+    fn from(fd: OwnedFd) -> Self {
+        SevGuest {
+            fd,
+            msg_version: Self::MSG_VERSION,
+        }
+    }
+}
+
+impl From<SevGuest> for OwnedFd {
+    fn from(guest: SevGuest) -> Self {
+        guest.fd
+    }
+}
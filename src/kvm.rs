@@ -1,18 +1,27 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::error::{Error, Indeterminate};
-use crate::sev::Id;
+use crate::device::SevDevice;
+use crate::error::{CommandError, Error, Indeterminate, InputError};
+use crate::sev::{FirmwareCommand, GuestPolicy, Id, SevPolicy};
+use crate::util::{check_pages, impl_pod, probe, try_len_u32, try_u32, UserPtr};
+use crate::{assert_layout, impl_const_id};
 
 use iocuddle::*;
 
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind};
 use std::marker::PhantomData;
-use std::os::raw::c_ulong;
-use std::os::unix::io::AsRawFd;
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
+use std::ptr::NonNull;
 
 /// The KVM iocuddle group.
 pub const KVM: Group = Group::new(0xAE);
 pub const ENC_OP: Ioctl<WriteRead, &c_ulong> = unsafe { KVM.write_read(0xBA) };
 
+/// The ID of a command issued to an SEV guest through `KVM_MEMORY_ENCRYPT_OP`.
+pub trait VmId: Id {}
+
 // These two ioctls are specified as read, although they write.
 // To compensate for the reference `Group::read` returns, specify
 // the write with a reference, too.
@@ -25,6 +34,119 @@ pub const ENC_REG_REGION: Ioctl<Write, &KvmEncRegion> =
 pub const ENC_UNREG_REGION: Ioctl<Write, &KvmEncRegion> =
     unsafe { KVM.read::<KvmEncRegion>(0xBC).lie() };
 
+/// Corresponds to the `KVM_SET_USER_MEMORY_REGION2` ioctl
+pub const SET_USER_MEMORY_REGION2: Ioctl<Write, &KvmUserspaceMemoryRegion2> =
+    unsafe { KVM.write(0x49) };
+
+/// Corresponds to the `KVM_SET_MEMORY_ATTRIBUTES` ioctl
+pub const SET_MEMORY_ATTRIBUTES: Ioctl<Write, &KvmMemoryAttributes> = unsafe { KVM.write(0xD2) };
+
+/// Corresponds to the `KVM_CREATE_GUEST_MEMFD` ioctl, which returns the new
+/// guest_memfd file descriptor on success.
+pub const CREATE_GUEST_MEMFD: Ioctl<WriteRead, &KvmCreateGuestMemfd> =
+    unsafe { KVM.write_read(0xD4) };
+
+/// The `KVM_MEM_GUEST_MEMFD` memory slot flag.
+pub const MEM_GUEST_MEMFD: u32 = 1 << 2;
+
+/// The `KVM_MEMORY_ATTRIBUTE_PRIVATE` memory attribute.
+pub const MEMORY_ATTRIBUTE_PRIVATE: u64 = 1 << 3;
+
+/// The granularity at which KVM tracks memory attributes.
+const PAGE_SIZE: u64 = 4096;
+
+/// Corresponds to the `KVM_CHECK_EXTENSION` ioctl, which may be issued on
+/// either the KVM system file descriptor or a VM file descriptor.
+pub const CHECK_EXTENSION: Ioctl<Write, c_int> = unsafe { Ioctl::classic(0xAE03) };
+
+/// The `KVM_CAP_VM_COPY_ENC_CONTEXT_FROM` capability.
+pub const CAP_VM_COPY_ENC_CONTEXT_FROM: c_int = 197;
+
+/// The `KVM_CAP_VM_MOVE_ENC_CONTEXT_FROM` capability.
+pub const CAP_VM_MOVE_ENC_CONTEXT_FROM: c_int = 206;
+
+/// The `KVM_CAP_MEMORY_ATTRIBUTES` capability.
+pub const CAP_MEMORY_ATTRIBUTES: c_int = 233;
+
+/// The `KVM_CAP_GUEST_MEMFD` capability.
+pub const CAP_GUEST_MEMFD: c_int = 234;
+
+/// The `KVM_CAP_VM_TYPES` capability.
+pub const CAP_VM_TYPES: c_int = 235;
+
+/// The `KVM_X86_DEFAULT_VM` VM type.
+pub const X86_DEFAULT_VM: u32 = 0;
+
+/// The `KVM_X86_SEV_VM` VM type.
+pub const X86_SEV_VM: u32 = 2;
+
+/// The `KVM_X86_SEV_ES_VM` VM type.
+pub const X86_SEV_ES_VM: u32 = 3;
+
+/// The `KVM_X86_SNP_VM` VM type.
+pub const X86_SNP_VM: u32 = 4;
+
+/// The SEV-related capabilities advertised by KVM.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KvmSevCaps {
+    /// SEV VMs may be created (`KVM_X86_SEV_VM`).
+    pub sev: bool,
+
+    /// SEV-ES VMs may be created (`KVM_X86_SEV_ES_VM`).
+    pub sev_es: bool,
+
+    /// SEV-SNP VMs may be created (`KVM_X86_SNP_VM`).
+    pub snp: bool,
+
+    /// Guest memory may be backed by `KVM_CREATE_GUEST_MEMFD`.
+    pub guest_memfd: bool,
+
+    /// The memory attributes supported by `KVM_SET_MEMORY_ATTRIBUTES`.
+    pub memory_attributes: u64,
+
+    /// The encryption context of a VM may be shared with another VM.
+    pub copy_enc_context: bool,
+
+    /// The encryption context of a VM may be moved to another VM.
+    pub move_enc_context: bool,
+}
+
+impl KvmSevCaps {
+    /// The VM type to pass to `KVM_CREATE_VM` for a VM of the given mode.
+    ///
+    /// Kernels which do not advertise a dedicated VM type for the mode require
+    /// a default VM, which is then initialized through the legacy commands.
+    pub fn vm_type(&self, mode: SevMode) -> u32 {
+        match mode {
+            SevMode::Sev if self.sev => X86_SEV_VM,
+            SevMode::SevEs if self.sev_es => X86_SEV_ES_VM,
+            SevMode::Snp if self.snp => X86_SNP_VM,
+            _ => X86_DEFAULT_VM,
+        }
+    }
+}
+
+/// Probe the SEV-related capabilities of KVM.
+///
+/// SEV support is derived from the VM types advertised by `KVM_CAP_VM_TYPES`,
+/// so kernels which predate that capability report no SEV support here even
+/// though they may allow SEV to be initialized on a default VM.
+pub fn capabilities(kvm: &impl AsFd) -> io::Result<KvmSevCaps> {
+    let kvm = &mut kvm.as_fd();
+    let vm_types = CHECK_EXTENSION.ioctl(kvm, CAP_VM_TYPES)?;
+    let has = |vm_type: u32| vm_types & (1 << vm_type) != 0;
+
+    Ok(KvmSevCaps {
+        sev: has(X86_SEV_VM),
+        sev_es: has(X86_SEV_ES_VM),
+        snp: has(X86_SNP_VM),
+        guest_memfd: CHECK_EXTENSION.ioctl(kvm, CAP_GUEST_MEMFD)? != 0,
+        memory_attributes: CHECK_EXTENSION.ioctl(kvm, CAP_MEMORY_ATTRIBUTES)? as _,
+        copy_enc_context: CHECK_EXTENSION.ioctl(kvm, CAP_VM_COPY_ENC_CONTEXT_FROM)? != 0,
+        move_enc_context: CHECK_EXTENSION.ioctl(kvm, CAP_VM_MOVE_ENC_CONTEXT_FROM)? != 0,
+    })
+}
+
 /// The Rust-flavored, FFI-friendly version of `struct sev_issue_cmd` which is
 /// used to pass arguments to the SEV ioctl implementation.
 ///
@@ -32,22 +154,27 @@ pub const ENC_UNREG_REGION: Ioctl<Write, &KvmEncRegion> =
 #[repr(C)]
 pub struct Command<'a, T: Id> {
     code: u32,
-    data: u64,
+    data: UserPtr<T>,
     error: u32,
     sev_fd: u32,
-    phantom: PhantomData<&'a T>,
+    phantom: PhantomData<(&'a T, BorrowedFd<'a>)>,
 }
 
+assert_layout!(Command<'static, Init>, 24, 8);
+
 impl<'a, T: Id> Command<'a, T> {
     /// Create an SEV-SNP command with the expectation that the host platform/kernel will write to
     /// the caller's address space either to the data held in the `Command.subcmd` field or some
     /// other region specified by the `Command.subcmd` field.
-    pub fn from_mut(sev: &'a mut impl AsRawFd, subcmd: &'a mut T) -> Self {
+    ///
+    /// The `/dev/sev` file descriptor stays borrowed for as long as the command
+    /// lives, so it cannot be closed before the command is issued.
+    pub fn from_mut(sev: &'a impl AsFd, subcmd: &'a mut T) -> Self {
         Command {
             code: T::ID,
-            data: subcmd as *mut T as u64,
+            data: UserPtr::from_mut(subcmd),
             error: 0,
-            sev_fd: sev.as_raw_fd() as _,
+            sev_fd: sev.as_fd().as_raw_fd() as _,
             phantom: PhantomData,
         }
     }
@@ -56,23 +183,22 @@ impl<'a, T: Id> Command<'a, T> {
     /// the caller's address space in its response. Note: this does not actually prevent the host
     /// platform/kernel from writing to the caller's address space if it wants to. This is primarily
     /// a semantic tool for programming against the SEV-SNP ioctl API.
-    pub fn from(sev: &'a mut impl AsRawFd, subcmd: &'a T) -> Self {
+    pub fn from(sev: &'a impl AsFd, subcmd: &'a T) -> Self {
         Command {
             code: T::ID,
-            data: subcmd as *const T as u64,
+            data: UserPtr::from_ref(subcmd),
             error: 0,
-            sev_fd: sev.as_raw_fd() as _,
+            sev_fd: sev.as_fd().as_raw_fd() as _,
             phantom: PhantomData,
         }
     }
+}
 
-    /// Rather than relying on status codes from the Linux kernel, match the specific error code
-    /// returned by the SNP firmware to output errors in more detail.
-    pub fn encapsulate(&self, err: std::io::Error) -> Indeterminate<Error> {
-        match self.error {
-            0 => Indeterminate::<Error>::from(err),
-            _ => Indeterminate::<Error>::from(self.error as u32),
-        }
+impl<T: Id> FirmwareCommand for Command<'_, T> {
+    type Subcommand = T;
+
+    fn status(&self) -> u32 {
+        self.error
     }
 }
 
@@ -80,18 +206,1542 @@ impl<'a, T: Id> Command<'a, T> {
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct KvmEncRegion<'a> {
-    addr: u64,
+    addr: UserPtr<[u8]>,
     size: u64,
     phantom: PhantomData<&'a [u8]>,
 }
 
+assert_layout!(KvmEncRegion<'static>, 16, 8);
+
 impl<'a> KvmEncRegion<'a> {
     /// Create a new `KvmEncRegion` referencing some memory assigned to the virtual machine.
     pub fn new(data: &'a [u8]) -> Self {
         Self {
-            addr: data.as_ptr() as _,
+            addr: UserPtr::from_ref(data),
             size: data.len() as _,
             phantom: PhantomData,
         }
     }
+
+    /// Create a new `KvmEncRegion` from a raw pointer and length, such as a
+    /// mapping of guest memory which is not owned by Rust.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `addr` points to a mapping of at least
+    /// `size` bytes which remains valid for the lifetime `'a`.
+    pub unsafe fn from_raw(addr: *const u8, size: usize) -> Self {
+        Self {
+            addr: UserPtr::from_ptr(std::ptr::slice_from_raw_parts(addr, size)),
+            size: size as _,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Create a new `KvmEncRegion` from a non-null pointer and length.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for [`KvmEncRegion::from_raw`] apply.
+    pub unsafe fn from_non_null(addr: NonNull<u8>, size: usize) -> Self {
+        Self::from_raw(addr.as_ptr(), size)
+    }
+}
+
+impl<'a> From<&'a [u8]> for KvmEncRegion<'a> {
+    #[inline]
+    fn from(data: &'a [u8]) -> Self {
+        Self::new(data)
+    }
+}
+
+/// Corresponds to the kernel struct `kvm_userspace_memory_region2`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KvmUserspaceMemoryRegion2 {
+    slot: u32,
+    flags: u32,
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    guest_memfd_offset: u64,
+    guest_memfd: u32,
+    pad1: u32,
+    pad2: [u64; 14],
+}
+
+assert_layout!(KvmUserspaceMemoryRegion2, 160, 8);
+
+impl KvmUserspaceMemoryRegion2 {
+    /// Create a new `KvmUserspaceMemoryRegion2` for a memory slot whose shared
+    /// pages are backed by the mapping at `userspace_addr` and whose private
+    /// pages are backed by `guest_memfd`, starting at `guest_memfd_offset`.
+    pub fn new(
+        slot: u32,
+        guest_phys_addr: u64,
+        memory_size: u64,
+        userspace_addr: u64,
+        guest_memfd: BorrowedFd<'_>,
+        guest_memfd_offset: u64,
+    ) -> Self {
+        Self {
+            slot,
+            flags: MEM_GUEST_MEMFD,
+            guest_phys_addr,
+            memory_size,
+            userspace_addr,
+            guest_memfd_offset,
+            guest_memfd: guest_memfd.as_raw_fd() as _,
+            pad1: 0,
+            pad2: [0; 14],
+        }
+    }
+}
+
+/// Corresponds to the kernel struct `kvm_memory_attributes`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct KvmMemoryAttributes {
+    address: u64,
+    size: u64,
+    attributes: u64,
+    flags: u64,
+}
+
+assert_layout!(KvmMemoryAttributes, 32, 8);
+
+impl KvmMemoryAttributes {
+    /// Create a new `KvmMemoryAttributes` which sets the attributes of the
+    /// guest physical range starting at `address`.
+    pub fn new(address: u64, size: u64, attributes: u64) -> Self {
+        Self {
+            address,
+            size,
+            attributes,
+            flags: 0,
+        }
+    }
+
+    /// Create a new `KvmMemoryAttributes` which converts the guest physical
+    /// range of `size` bytes starting at `gpa` to private or shared memory.
+    ///
+    /// Attributes are tracked per page, so the range must start and end on a
+    /// page boundary rather than converting bytes outside of it, and it must
+    /// not extend past the end of the guest physical address space.
+    pub fn range(gpa: u64, size: u64, private: bool) -> Result<Self, InputError> {
+        if gpa % PAGE_SIZE != 0 {
+            return Err(InputError::Misaligned {
+                addr: gpa as _,
+                align: PAGE_SIZE as _,
+            });
+        }
+
+        if size % PAGE_SIZE != 0 {
+            return Err(InputError::BadLength {
+                len: size as _,
+                multiple: PAGE_SIZE as _,
+            });
+        }
+
+        if gpa.checked_add(size).is_none() {
+            return Err(InputError::TooLong {
+                len: size as _,
+                max: (u64::MAX - gpa) as _,
+            });
+        }
+
+        let attributes = if private { MEMORY_ATTRIBUTE_PRIVATE } else { 0 };
+        Ok(Self::new(gpa, size, attributes))
+    }
+
+    /// The guest physical address of the first page in the range.
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// The size of the range in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Corresponds to the kernel struct `kvm_create_guest_memfd`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct KvmCreateGuestMemfd {
+    size: u64,
+    flags: u64,
+    reserved: [u64; 6],
+}
+
+assert_layout!(KvmCreateGuestMemfd, 64, 8);
+
+impl KvmCreateGuestMemfd {
+    /// Create a new `KvmCreateGuestMemfd` for a guest_memfd of the given size.
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            ..Default::default()
+        }
+    }
+}
+
+/// Extension methods for issuing SEV commands through a VM file descriptor.
+///
+/// This trait is implemented for every type which exposes a file descriptor,
+/// so it is enough to bring it into scope to use it on a VM fd.
+pub trait KvmEncOps: AsFd + Sized {
+    /// Issue an SEV command through `KVM_MEMORY_ENCRYPT_OP`, reporting a
+    /// failure along with the status reported by the firmware.
+    fn sev_cmd<T: VmId>(&self, cmd: &mut Command<T>) -> Result<(), CommandError> {
+        let op: Ioctl<WriteRead, &Command<T>> = unsafe { ENC_OP.lie() };
+        op.ioctl(&mut self.as_fd(), cmd).map_err(|e| cmd.error(e))?;
+        Ok(())
+    }
+
+    /// Register a region of guest memory with `KVM_MEMORY_ENCRYPT_REG_REGION`.
+    ///
+    /// The region is unregistered when the returned guard is dropped.
+    fn register_region<'a>(
+        &'a self,
+        region: impl Into<KvmEncRegion<'a>>,
+    ) -> io::Result<RegisteredRegion<'a>> {
+        let region = region.into();
+        let vm = self.as_fd();
+        ENC_REG_REGION.ioctl(&mut vm.as_fd(), &region)?;
+
+        Ok(RegisteredRegion { vm, region })
+    }
+
+    /// Register several regions of guest memory at once.
+    ///
+    /// If any registration fails, the regions which were already registered
+    /// are unregistered again before the error is returned.
+    fn register_regions<'a, I>(&'a self, regions: I) -> io::Result<Vec<RegisteredRegion<'a>>>
+    where
+        I: IntoIterator,
+        I::Item: Into<KvmEncRegion<'a>>,
+    {
+        regions
+            .into_iter()
+            .map(|region| self.register_region(region))
+            .collect()
+    }
+
+    /// Initialize the SEV platform context of a VM created with the VM type
+    /// returned by [`KvmSevCaps::vm_type`] for the same mode.
+    ///
+    /// `KVM_SEV_INIT2` is used when the kernel supports a dedicated VM type for
+    /// the mode; otherwise the legacy `KVM_SEV_INIT` or `KVM_SEV_ES_INIT` is
+    /// used and `init` is ignored. SEV-SNP has no legacy initialization path.
+    fn sev_init(
+        &self,
+        sev: &impl AsFd,
+        caps: &KvmSevCaps,
+        mode: SevMode,
+        init: &Init2,
+    ) -> Result<(), Indeterminate<Error>> {
+        if caps.vm_type(mode) != X86_DEFAULT_VM {
+            return Ok(self.sev_cmd(&mut Command::from(sev, init))?);
+        }
+
+        match mode {
+            SevMode::Sev => Ok(self.sev_cmd(&mut Command::from(sev, &Init))?),
+            SevMode::SevEs => Ok(self.sev_cmd(&mut Command::from(sev, &EsInit))?),
+            SevMode::Snp => Err(Indeterminate::Known(Error::Unsupported)),
+        }
+    }
+
+    /// Convert a guest physical range to private or shared memory with
+    /// `KVM_SET_MEMORY_ATTRIBUTES`; see [`KvmMemoryAttributes::range`].
+    ///
+    /// The ioctl is issued once and any failure is returned as is; wrap the
+    /// conversion in a retry loop if the kernel may report it as busy.
+    fn set_memory_private(&self, gpa: u64, size: u64, private: bool) -> io::Result<()> {
+        let attrs = KvmMemoryAttributes::range(gpa, size, private)?;
+        if attrs.size() == 0 {
+            return Ok(());
+        }
+
+        SET_MEMORY_ATTRIBUTES.ioctl(&mut self.as_fd(), &attrs)?;
+        Ok(())
+    }
+}
+
+impl<F: AsFd> KvmEncOps for F {}
+
+/// A VM file descriptor paired with the `/dev/sev` file descriptor which
+/// authorizes the SEV commands issued through it.
+#[derive(Debug)]
+pub struct SevVm<V, S> {
+    vm: V,
+    sev: S,
+}
+
+impl<V: AsFd, S: AsFd> SevVm<V, S> {
+    /// Pair a VM file descriptor with a `/dev/sev` file descriptor.
+    pub fn new(vm: V, sev: S) -> Self {
+        Self { vm, sev }
+    }
+
+    /// The VM file descriptor.
+    pub fn vm(&mut self) -> &mut V {
+        &mut self.vm
+    }
+
+    /// The `/dev/sev` file descriptor.
+    pub fn sev(&mut self) -> &mut S {
+        &mut self.sev
+    }
+
+    /// Give back the VM and `/dev/sev` file descriptors.
+    pub fn into_inner(self) -> (V, S) {
+        (self.vm, self.sev)
+    }
 }
+
+impl<V: AsFd, S: AsFd, T: VmId> SevDevice<T> for SevVm<V, S> {
+    type Error = CommandError;
+
+    fn issue_ioctl(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        let mut cmd = Command::from_mut(&self.sev, subcmd);
+        self.vm.sev_cmd(&mut cmd)
+    }
+}
+
+/// A region of guest memory registered with the kernel, which pins the
+/// backing pages for as long as the registration lasts.
+///
+/// The region is unregistered with `KVM_MEMORY_ENCRYPT_UNREG_REGION` when the
+/// guard is dropped. The guard borrows the VM file descriptor, so the VM
+/// cannot be closed while the registration lasts.
+#[derive(Debug)]
+pub struct RegisteredRegion<'a> {
+    vm: BorrowedFd<'a>,
+    region: KvmEncRegion<'a>,
+}
+
+impl RegisteredRegion<'_> {
+    /// Unregister the region, reporting any failure to do so.
+    pub fn unregister(self) -> io::Result<()> {
+        let this = std::mem::ManuallyDrop::new(self);
+        ENC_UNREG_REGION.ioctl(&mut this.vm.as_fd(), &this.region)?;
+        Ok(())
+    }
+}
+
+impl Drop for RegisteredRegion<'_> {
+    fn drop(&mut self) {
+        let _ = ENC_UNREG_REGION.ioctl(&mut self.vm.as_fd(), &self.region);
+    }
+}
+
+/// Initialize the SEV platform context.
+///
+/// Corresponds to the `KVM_SEV_INIT` command, which carries no parameters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Init;
+
+/// Initialize the SEV-ES platform context.
+///
+/// Corresponds to the `KVM_SEV_ES_INIT` command, which carries no parameters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EsInit;
+
+/// Initialize the SEV platform context of a VM created with one of the SEV
+/// VM types (`KVM_X86_SEV_VM`, `KVM_X86_SEV_ES_VM` or `KVM_X86_SNP_VM`).
+///
+/// Corresponds to the kernel struct `kvm_sev_init` for the `KVM_SEV_INIT2`
+/// command, which supersedes `KVM_SEV_INIT` and `KVM_SEV_ES_INIT`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Init2 {
+    vmsa_features: u64,
+    flags: u32,
+    ghcb_version: u16,
+    pad1: u16,
+    pad2: [u32; 8],
+}
+
+assert_layout!(Init2, 48, 8);
+
+impl Init2 {
+    /// Create a new `Init2` with the SEV features to enable in the VMSA of
+    /// each vCPU and the maximum GHCB protocol version to support (zero
+    /// selects the kernel's default).
+    pub fn new(vmsa_features: u64, ghcb_version: u16) -> Self {
+        Self {
+            vmsa_features,
+            ghcb_version,
+            ..Default::default()
+        }
+    }
+}
+
+/// The flavor of SEV a VM is launched with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SevMode {
+    /// Secure Encrypted Virtualization.
+    Sev,
+
+    /// SEV with Encrypted State.
+    SevEs,
+
+    /// SEV with Secure Nested Paging.
+    Snp,
+}
+
+/// Start the launch process for an SEV guest.
+///
+/// Corresponds to the kernel struct `kvm_sev_launch_start`. The guest owner's
+/// Diffie-Hellman certificate and session parameters are optional; when they
+/// are omitted the firmware generates the launch secrets itself.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LaunchStart<'a> {
+    handle: u32,
+    policy: u32,
+    dh_uaddr: UserPtr<[u8]>,
+    dh_len: u32,
+    pad0: u32,
+    session_uaddr: UserPtr<[u8]>,
+    session_len: u32,
+    pad1: u32,
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(LaunchStart<'static>, 40, 8);
+
+impl<'a> LaunchStart<'a> {
+    /// Create a new `LaunchStart` for a guest with the given policy, optionally
+    /// referencing the guest owner's DH certificate and session blob.
+    pub fn new(
+        policy: SevPolicy,
+        dh: Option<&'a [u8]>,
+        session: Option<&'a [u8]>,
+    ) -> Result<Self, InputError> {
+        let (dh_uaddr, dh_len) = uaddr(dh)?;
+        let (session_uaddr, session_len) = uaddr(session)?;
+
+        Ok(Self {
+            handle: 0,
+            policy: policy.bits(),
+            dh_uaddr,
+            dh_len,
+            pad0: 0,
+            session_uaddr,
+            session_len,
+            pad1: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The guest handle assigned by the firmware once the command completes.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+}
+
+/// Encrypt a region of guest memory in place and add it to the launch measurement.
+///
+/// Corresponds to the kernel struct `kvm_sev_launch_update_data`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LaunchUpdateData<'a> {
+    uaddr: UserPtr<[u8]>,
+    len: u32,
+    pad0: u32,
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(LaunchUpdateData<'static>, 16, 8);
+
+impl<'a> LaunchUpdateData<'a> {
+    /// Create a new `LaunchUpdateData` referencing guest memory to be encrypted.
+    pub fn new(data: &'a [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            uaddr: UserPtr::from_ref(data),
+            len: try_len_u32(data)?,
+            pad0: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// Retrieve the launch measurement of an SEV guest.
+///
+/// Corresponds to the kernel struct `kvm_sev_launch_measure`. The firmware
+/// writes the measurement into the referenced buffer and updates the length;
+/// [`LaunchMeasure::fetch`] takes care of sizing the buffer.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct LaunchMeasure<'a> {
+    uaddr: UserPtr<[u8]>,
+    len: u32,
+    pad0: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(LaunchMeasure<'static>, 16, 8);
+
+impl<'a> LaunchMeasure<'a> {
+    /// Create a new `LaunchMeasure` referencing the buffer which receives the
+    /// measurement.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            uaddr: UserPtr::from_mut(buf),
+            len: try_len_u32(buf)?,
+            pad0: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the measurement, as reported by the firmware.
+    pub fn length(&self) -> usize {
+        self.len as _
+    }
+}
+
+impl LaunchMeasure<'_> {
+    /// Query the length of the launch measurement, then retrieve it.
+    pub fn fetch(vm: &mut impl AsFd, sev: &impl AsFd) -> Result<Vec<u8>, Indeterminate<Error>> {
+        let mut query = LaunchMeasure::default();
+        let mut cmd = Command::from_mut(sev, &mut query);
+        probe(vm.sev_cmd(&mut cmd))?;
+
+        let mut buf = vec![0u8; query.length()];
+        let mut measure = LaunchMeasure::new(&mut buf)?;
+        let mut cmd = Command::from_mut(sev, &mut measure);
+        vm.sev_cmd(&mut cmd)?;
+
+        let len = measure.length();
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+/// Encrypt the VMSA of every vCPU and add it to the launch measurement (SEV-ES only).
+///
+/// Corresponds to the `KVM_SEV_LAUNCH_UPDATE_VMSA` command, which carries no
+/// parameters; the kernel synchronizes the register state of each vCPU into
+/// its VMSA before encrypting it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchUpdateVmsa;
+
+/// Inject a secret into the guest during launch.
+///
+/// Corresponds to the kernel struct `kvm_sev_launch_secret`. The packet header
+/// and transport buffer come from the guest owner, while the guest buffer is
+/// the region of guest memory that receives the decrypted secret.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LaunchSecret<'a> {
+    hdr_uaddr: UserPtr<[u8]>,
+    hdr_len: u32,
+    pad0: u32,
+    guest_uaddr: UserPtr<[u8]>,
+    guest_len: u32,
+    pad1: u32,
+    trans_uaddr: UserPtr<[u8]>,
+    trans_len: u32,
+    pad2: u32,
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(LaunchSecret<'static>, 48, 8);
+
+impl<'a> LaunchSecret<'a> {
+    /// Create a new `LaunchSecret` from the packet header, the destination in
+    /// guest memory and the encrypted transport buffer.
+    pub fn new(hdr: &'a [u8], guest: &'a [u8], trans: &'a [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            hdr_uaddr: UserPtr::from_ref(hdr),
+            hdr_len: try_len_u32(hdr)?,
+            pad0: 0,
+            guest_uaddr: UserPtr::from_ref(guest),
+            guest_len: try_len_u32(guest)?,
+            pad1: 0,
+            trans_uaddr: UserPtr::from_ref(trans),
+            trans_len: try_len_u32(trans)?,
+            pad2: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// Complete the launch flow and transition the guest into the running state.
+///
+/// Corresponds to the `KVM_SEV_LAUNCH_FINISH` command, which carries no parameters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchFinish;
+
+/// Start the migration of an SEV guest to another platform.
+///
+/// Corresponds to the kernel struct `kvm_sev_send_start`. The certificates
+/// describe the target platform, while the session buffer receives the
+/// session parameters generated by the firmware. Issuing the command with an
+/// empty session buffer queries its required length instead;
+/// [`SendStart::fetch`] takes care of sizing the buffer.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SendStart<'a> {
+    policy: u32,
+    pad0: u32,
+    pdh_cert_uaddr: UserPtr<[u8]>,
+    pdh_cert_len: u32,
+    pad1: u32,
+    plat_certs_uaddr: UserPtr<[u8]>,
+    plat_certs_len: u32,
+    pad2: u32,
+    amd_certs_uaddr: UserPtr<[u8]>,
+    amd_certs_len: u32,
+    pad3: u32,
+    session_uaddr: UserPtr<[u8]>,
+    session_len: u32,
+    pad4: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(SendStart<'static>, 72, 8);
+
+impl<'a> SendStart<'a> {
+    /// Create a new `SendStart` from the target's PDH certificate, its
+    /// platform certificate chain (PEK, OCA and CEK) and the AMD certificate
+    /// chain (ASK and ARK), referencing the buffer which receives the session.
+    pub fn new(
+        pdh_cert: &'a [u8],
+        plat_certs: &'a [u8],
+        amd_certs: &'a [u8],
+        session: &'a mut [u8],
+    ) -> Result<Self, InputError> {
+        Ok(Self {
+            pdh_cert_uaddr: UserPtr::from_ref(pdh_cert),
+            pdh_cert_len: try_len_u32(pdh_cert)?,
+            plat_certs_uaddr: UserPtr::from_ref(plat_certs),
+            plat_certs_len: try_len_u32(plat_certs)?,
+            amd_certs_uaddr: UserPtr::from_ref(amd_certs),
+            amd_certs_len: try_len_u32(amd_certs)?,
+            session_uaddr: UserPtr::from_mut(session),
+            session_len: try_len_u32(session)?,
+            ..Default::default()
+        })
+    }
+
+    /// The policy of the guest being sent, as reported by the firmware.
+    pub fn policy(&self) -> SevPolicy {
+        SevPolicy::from_bits_truncate(self.policy)
+    }
+
+    /// The length of the session parameters, as reported by the firmware.
+    pub fn session_length(&self) -> usize {
+        self.session_len as _
+    }
+}
+
+impl SendStart<'_> {
+    /// Query the length of the session parameters, then start the migration
+    /// and retrieve them.
+    pub fn fetch(
+        vm: &mut impl AsFd,
+        sev: &impl AsFd,
+        pdh_cert: &[u8],
+        plat_certs: &[u8],
+        amd_certs: &[u8],
+    ) -> Result<Vec<u8>, Indeterminate<Error>> {
+        let mut query = SendStart::new(pdh_cert, plat_certs, amd_certs, &mut [])?;
+        let mut cmd = Command::from_mut(sev, &mut query);
+        probe(vm.sev_cmd(&mut cmd))?;
+
+        let mut session = vec![0u8; query.session_length()];
+        let mut start = SendStart::new(pdh_cert, plat_certs, amd_certs, &mut session)?;
+        let mut cmd = Command::from_mut(sev, &mut start);
+        vm.sev_cmd(&mut cmd)?;
+
+        let len = start.session_length();
+        session.truncate(len);
+        Ok(session)
+    }
+}
+
+/// Encrypt a region of guest memory with the transport keys for migration.
+///
+/// Corresponds to the kernel struct `kvm_sev_send_update_data`. The firmware
+/// writes the packet header and the re-encrypted guest memory into the header
+/// and transport buffers. Issuing the command with empty header and transport
+/// buffers queries their required lengths instead.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SendUpdateData<'a> {
+    hdr_uaddr: UserPtr<[u8]>,
+    hdr_len: u32,
+    pad0: u32,
+    guest_uaddr: UserPtr<[u8]>,
+    guest_len: u32,
+    pad1: u32,
+    trans_uaddr: UserPtr<[u8]>,
+    trans_len: u32,
+    pad2: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(SendUpdateData<'static>, 48, 8);
+
+impl<'a> SendUpdateData<'a> {
+    /// Create a new `SendUpdateData` referencing the guest memory to be sent
+    /// and the buffers which receive the packet header and transport data.
+    pub fn new(
+        hdr: &'a mut [u8],
+        guest: &'a [u8],
+        trans: &'a mut [u8],
+    ) -> Result<Self, InputError> {
+        Ok(Self {
+            hdr_uaddr: UserPtr::from_mut(hdr),
+            hdr_len: try_len_u32(hdr)?,
+            pad0: 0,
+            guest_uaddr: UserPtr::from_ref(guest),
+            guest_len: try_len_u32(guest)?,
+            pad1: 0,
+            trans_uaddr: UserPtr::from_mut(trans),
+            trans_len: try_len_u32(trans)?,
+            pad2: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the packet header, as reported by the firmware.
+    pub fn hdr_length(&self) -> usize {
+        self.hdr_len as _
+    }
+
+    /// The length of the transport data, as reported by the firmware.
+    pub fn trans_length(&self) -> usize {
+        self.trans_len as _
+    }
+}
+
+/// Encrypt the VMSA of a vCPU with the transport keys for migration (SEV-ES only).
+///
+/// Mirrors `struct kvm_sev_send_update_vmsa` from the SEV-ES live migration
+/// series, which mainline kernels do not implement yet; issuing this command
+/// there fails with `EINVAL`. The firmware writes the packet header and the
+/// re-encrypted VMSA into the header and transport buffers.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SendUpdateVmsa<'a> {
+    vcpu_id: u32,
+    pad0: u32,
+    hdr_uaddr: UserPtr<[u8]>,
+    hdr_len: u32,
+    pad1: u32,
+    trans_uaddr: UserPtr<[u8]>,
+    trans_len: u32,
+    pad2: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(SendUpdateVmsa<'static>, 40, 8);
+
+impl<'a> SendUpdateVmsa<'a> {
+    /// Create a new `SendUpdateVmsa` for the given vCPU, referencing the
+    /// buffers which receive the packet header and transport data.
+    pub fn new(vcpu_id: u32, hdr: &'a mut [u8], trans: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            vcpu_id,
+            pad0: 0,
+            hdr_uaddr: UserPtr::from_mut(hdr),
+            hdr_len: try_len_u32(hdr)?,
+            pad1: 0,
+            trans_uaddr: UserPtr::from_mut(trans),
+            trans_len: try_len_u32(trans)?,
+            pad2: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the packet header, as reported by the firmware.
+    pub fn hdr_length(&self) -> usize {
+        self.hdr_len as _
+    }
+
+    /// The length of the transport data, as reported by the firmware.
+    pub fn trans_length(&self) -> usize {
+        self.trans_len as _
+    }
+}
+
+/// Complete the migration of an SEV guest.
+///
+/// Corresponds to the `KVM_SEV_SEND_FINISH` command, which carries no parameters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendFinish;
+
+/// Abort the migration of an SEV guest, returning it to the running state.
+///
+/// Corresponds to the `KVM_SEV_SEND_CANCEL` command, which carries no parameters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendCancel;
+
+/// Start the migration of an SEV guest from another platform.
+///
+/// Corresponds to the kernel struct `kvm_sev_receive_start`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ReceiveStart<'a> {
+    handle: u32,
+    policy: u32,
+    pdh_uaddr: UserPtr<[u8]>,
+    pdh_len: u32,
+    pad0: u32,
+    session_uaddr: UserPtr<[u8]>,
+    session_len: u32,
+    pad1: u32,
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(ReceiveStart<'static>, 40, 8);
+
+impl<'a> ReceiveStart<'a> {
+    /// Create a new `ReceiveStart` for a guest with the given policy from the
+    /// source's PDH certificate and the session parameters produced by
+    /// [`SendStart`].
+    pub fn new(policy: SevPolicy, pdh: &'a [u8], session: &'a [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            handle: 0,
+            policy: policy.bits(),
+            pdh_uaddr: UserPtr::from_ref(pdh),
+            pdh_len: try_len_u32(pdh)?,
+            pad0: 0,
+            session_uaddr: UserPtr::from_ref(session),
+            session_len: try_len_u32(session)?,
+            pad1: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The guest handle assigned by the firmware once the command completes.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+}
+
+/// Decrypt migrated data with the transport keys and re-encrypt it into guest
+/// memory.
+///
+/// Corresponds to the kernel struct `kvm_sev_receive_update_data`.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct ReceiveUpdateData<'a> {
+    hdr_uaddr: UserPtr<[u8]>,
+    hdr_len: u32,
+    pad0: u32,
+    guest_uaddr: UserPtr<[u8]>,
+    guest_len: u32,
+    pad1: u32,
+    trans_uaddr: UserPtr<[u8]>,
+    trans_len: u32,
+    pad2: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(ReceiveUpdateData<'static>, 48, 8);
+
+impl<'a> ReceiveUpdateData<'a> {
+    /// Create a new `ReceiveUpdateData` from the packet header and transport
+    /// data produced by [`SendUpdateData`], referencing the guest memory which
+    /// receives the data.
+    pub fn new(hdr: &'a [u8], guest: &'a mut [u8], trans: &'a [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            hdr_uaddr: UserPtr::from_ref(hdr),
+            hdr_len: try_len_u32(hdr)?,
+            pad0: 0,
+            guest_uaddr: UserPtr::from_mut(guest),
+            guest_len: try_len_u32(guest)?,
+            pad1: 0,
+            trans_uaddr: UserPtr::from_ref(trans),
+            trans_len: try_len_u32(trans)?,
+            pad2: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// Complete the migration of an SEV guest from another platform.
+///
+/// Corresponds to the `KVM_SEV_RECEIVE_FINISH` command, which carries no parameters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReceiveFinish;
+
+/// The state of an SEV guest, as reported by the firmware.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GuestState {
+    /// The guest is uninitialized.
+    Uninit,
+
+    /// The guest is currently being launched and plaintext data and VMCB
+    /// save areas are being imported.
+    LaunchUpdate,
+
+    /// The guest is currently being launched and ciphertext data is being
+    /// imported.
+    LaunchSecret,
+
+    /// The guest is fully launched or migrated in, and not being migrated out
+    /// to another machine.
+    Running,
+
+    /// The guest is currently being migrated out to another machine.
+    SendUpdate,
+
+    /// The guest is currently being migrated from another machine.
+    ReceiveUpdate,
+
+    /// The guest has been sent to another machine.
+    Sent,
+}
+
+impl From<u32> for Indeterminate<GuestState> {
+    #[inline]
+    fn from(state: u32) -> Indeterminate<GuestState> {
+        Indeterminate::Known(match state {
+            0 => GuestState::Uninit,
+            1 => GuestState::LaunchUpdate,
+            2 => GuestState::LaunchSecret,
+            3 => GuestState::Running,
+            4 => GuestState::SendUpdate,
+            5 => GuestState::ReceiveUpdate,
+            6 => GuestState::Sent,
+            _ => return Indeterminate::Unknown,
+        })
+    }
+}
+
+/// Query the status of an SEV guest.
+///
+/// Corresponds to the kernel struct `kvm_sev_guest_status`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct GuestStatus {
+    handle: u32,
+    policy: u32,
+    state: u32,
+}
+
+assert_layout!(GuestStatus, 12, 4);
+
+impl GuestStatus {
+    /// The firmware handle of the guest.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// The policy the guest was launched with.
+    pub fn policy(&self) -> SevPolicy {
+        SevPolicy::from_bits_truncate(self.policy)
+    }
+
+    /// The current state of the guest.
+    pub fn state(&self) -> Indeterminate<GuestState> {
+        self.state.into()
+    }
+}
+
+/// The length of a debug transfer between `src` and `dst`, which must match
+/// so that a partial transfer cannot go unnoticed.
+fn dbg_len(src: &[u8], dst: &[u8]) -> Result<u32, InputError> {
+    if src.len() != dst.len() {
+        return Err(InputError::LengthMismatch {
+            src: src.len(),
+            dst: dst.len(),
+        });
+    }
+
+    try_u32(src.len())
+}
+
+/// Decrypt guest memory for debugging.
+///
+/// Corresponds to the kernel struct `kvm_sev_dbg` for the `KVM_SEV_DBG_DECRYPT`
+/// command. Only guests whose policy allows debugging may be inspected.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct DbgDecrypt<'a> {
+    src_uaddr: UserPtr<[u8]>,
+    dst_uaddr: UserPtr<[u8]>,
+    len: u32,
+    pad0: u32,
+    phantom: PhantomData<(&'a [u8], &'a mut [u8])>,
+}
+
+assert_layout!(DbgDecrypt<'static>, 24, 8);
+
+impl<'a> DbgDecrypt<'a> {
+    /// Create a new `DbgDecrypt` which decrypts the guest memory in `src`
+    /// into `dst`, which must be of the same length.
+    pub fn new(src: &'a [u8], dst: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            len: dbg_len(src, dst)?,
+            src_uaddr: UserPtr::from_ref(src),
+            dst_uaddr: UserPtr::from_mut(dst),
+            pad0: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// Encrypt data into guest memory for debugging.
+///
+/// Corresponds to the kernel struct `kvm_sev_dbg` for the `KVM_SEV_DBG_ENCRYPT`
+/// command. Only guests whose policy allows debugging may be modified.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct DbgEncrypt<'a> {
+    src_uaddr: UserPtr<[u8]>,
+    dst_uaddr: UserPtr<[u8]>,
+    len: u32,
+    pad0: u32,
+    phantom: PhantomData<(&'a [u8], &'a mut [u8])>,
+}
+
+assert_layout!(DbgEncrypt<'static>, 24, 8);
+
+impl<'a> DbgEncrypt<'a> {
+    /// Create a new `DbgEncrypt` which encrypts the plaintext in `src` into
+    /// the guest memory in `dst`, which must be of the same length.
+    pub fn new(src: &'a [u8], dst: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            len: dbg_len(src, dst)?,
+            src_uaddr: UserPtr::from_ref(src),
+            dst_uaddr: UserPtr::from_mut(dst),
+            pad0: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// Export the platform's PDH certificate and certificate chain for a guest.
+///
+/// Mirrors the layout of `struct sev_user_data_pdh_cert_export` for the
+/// `KVM_SEV_CERT_EXPORT` command, which mainline kernels reserve but do not
+/// implement yet. When the buffers are too small the command fails with
+/// either `INVALID_LEN` or `EINVAL` after updating the lengths;
+/// [`CertExport::fetch`] takes care of sizing the buffers.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct CertExport<'a> {
+    pdh_cert_uaddr: UserPtr<[u8]>,
+    pdh_cert_len: u32,
+    pad0: u32,
+    cert_chain_uaddr: UserPtr<[u8]>,
+    cert_chain_len: u32,
+    pad1: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(CertExport<'static>, 32, 8);
+
+impl<'a> CertExport<'a> {
+    /// Create a new `CertExport` referencing the buffers which receive the
+    /// PDH certificate and the certificate chain.
+    pub fn new(pdh_cert: &'a mut [u8], cert_chain: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            pdh_cert_uaddr: UserPtr::from_mut(pdh_cert),
+            pdh_cert_len: try_len_u32(pdh_cert)?,
+            pad0: 0,
+            cert_chain_uaddr: UserPtr::from_mut(cert_chain),
+            cert_chain_len: try_len_u32(cert_chain)?,
+            pad1: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the PDH certificate, as reported by the firmware.
+    pub fn pdh_cert_length(&self) -> usize {
+        self.pdh_cert_len as _
+    }
+
+    /// The length of the certificate chain, as reported by the firmware.
+    pub fn cert_chain_length(&self) -> usize {
+        self.cert_chain_len as _
+    }
+}
+
+impl CertExport<'_> {
+    /// Query the lengths of the PDH certificate and the certificate chain,
+    /// then retrieve both.
+    pub fn fetch(
+        vm: &mut impl AsFd,
+        sev: &impl AsFd,
+    ) -> Result<(Vec<u8>, Vec<u8>), Indeterminate<Error>> {
+        let mut query = CertExport::new(&mut [], &mut [])?;
+        let mut cmd = Command::from_mut(sev, &mut query);
+        let result = vm.sev_cmd(&mut cmd);
+
+        match result {
+            Err(e)
+                if e.status() == 0
+                    && e.os_error().kind() == ErrorKind::InvalidInput
+                    && query.pdh_cert_length() > 0
+                    && query.cert_chain_length() > 0 => {}
+            result => probe(result)?,
+        }
+
+        let mut pdh_cert = vec![0u8; query.pdh_cert_length()];
+        let mut cert_chain = vec![0u8; query.cert_chain_length()];
+        let mut export = CertExport::new(&mut pdh_cert, &mut cert_chain)?;
+        let mut cmd = Command::from_mut(sev, &mut export);
+        vm.sev_cmd(&mut cmd)?;
+
+        let (pdh_cert_len, cert_chain_len) = (export.pdh_cert_length(), export.cert_chain_length());
+        pdh_cert.truncate(pdh_cert_len);
+        cert_chain.truncate(cert_chain_len);
+        Ok((pdh_cert, cert_chain))
+    }
+}
+
+/// Retrieve an attestation report for an SEV guest during launch.
+///
+/// Corresponds to the kernel struct `kvm_sev_attestation_report`. The
+/// firmware writes the report into the referenced buffer and updates the
+/// length; [`GetAttestationReport::fetch`] takes care of sizing the buffer.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct GetAttestationReport<'a> {
+    mnonce: [u8; 16],
+    uaddr: UserPtr<[u8]>,
+    len: u32,
+    pad0: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(GetAttestationReport<'static>, 32, 8);
+
+impl<'a> GetAttestationReport<'a> {
+    /// Create a new `GetAttestationReport` with the given nonce, referencing
+    /// the buffer which receives the report.
+    pub fn new(mnonce: [u8; 16], buf: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            mnonce,
+            uaddr: UserPtr::from_mut(buf),
+            len: try_len_u32(buf)?,
+            pad0: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the report, as reported by the firmware.
+    pub fn length(&self) -> usize {
+        self.len as _
+    }
+}
+
+impl GetAttestationReport<'_> {
+    /// Query the length of the attestation report, then retrieve it.
+    pub fn fetch(
+        vm: &mut impl AsFd,
+        sev: &impl AsFd,
+        mnonce: [u8; 16],
+    ) -> Result<Vec<u8>, Indeterminate<Error>> {
+        let mut query = GetAttestationReport::new(mnonce, &mut [])?;
+        let mut cmd = Command::from_mut(sev, &mut query);
+        probe(vm.sev_cmd(&mut cmd))?;
+
+        let mut buf = vec![0u8; query.length()];
+        let mut report = GetAttestationReport::new(mnonce, &mut buf)?;
+        let mut cmd = Command::from_mut(sev, &mut report);
+        vm.sev_cmd(&mut cmd)?;
+
+        let len = report.length();
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+/// Start the launch process for an SEV-SNP guest.
+///
+/// Corresponds to the kernel struct `kvm_sev_snp_launch_start`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SnpLaunchStart {
+    policy: u64,
+    gosvw: [u8; 16],
+    flags: u16,
+    pad0: [u8; 6],
+    pad1: [u64; 4],
+}
+
+assert_layout!(SnpLaunchStart, 64, 8);
+
+impl SnpLaunchStart {
+    /// Create a new `SnpLaunchStart` for a guest with the given policy and
+    /// guest OS visible workarounds (provided by the hypervisor).
+    pub fn new(policy: GuestPolicy, gosvw: [u8; 16]) -> Self {
+        Self {
+            policy: policy.into(),
+            gosvw,
+            ..Default::default()
+        }
+    }
+}
+
+/// The type of the pages inserted by an SNP launch update, as defined by the
+/// SEV-SNP firmware ABI specification.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageType {
+    /// A normal data page.
+    Normal = 0x1,
+
+    /// A VMSA page.
+    Vmsa = 0x2,
+
+    /// A page full of zeroes.
+    Zero = 0x3,
+
+    /// A page that is encrypted but not measured.
+    Unmeasured = 0x4,
+
+    /// A page for the firmware to store secrets for the guest.
+    Secrets = 0x5,
+
+    /// A page for the hypervisor to provide CPUID function values.
+    Cpuid = 0x6,
+}
+
+impl From<PageType> for u8 {
+    #[inline]
+    fn from(page_type: PageType) -> u8 {
+        page_type as _
+    }
+}
+
+impl TryFrom<u8> for PageType {
+    type Error = u8;
+
+    #[inline]
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x1 => PageType::Normal,
+            0x2 => PageType::Vmsa,
+            0x3 => PageType::Zero,
+            0x4 => PageType::Unmeasured,
+            0x5 => PageType::Secrets,
+            0x6 => PageType::Cpuid,
+            _ => return Err(value),
+        })
+    }
+}
+
+/// Insert pages into the guest's memory and add them to the launch digest.
+///
+/// Corresponds to the kernel struct `kvm_sev_snp_launch_update`. The kernel
+/// is not guaranteed to process the entire range in a single call; on return
+/// the fields describe the range which remains, so callers should reissue the
+/// command until [`SnpLaunchUpdate::remaining`] is zero.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SnpLaunchUpdate<'a> {
+    gfn_start: u64,
+    uaddr: UserPtr<[u8]>,
+    len: u64,
+    page_type: u8,
+    pad0: u8,
+    flags: u16,
+    pad1: u32,
+    pad2: [u64; 4],
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(SnpLaunchUpdate<'static>, 64, 8);
+
+impl<'a> SnpLaunchUpdate<'a> {
+    /// Create a new `SnpLaunchUpdate` which places `data` at the guest frame
+    /// number `gfn_start` as pages of the given type. The kernel requires
+    /// `data` to be page-aligned and a whole number of pages long, which an
+    /// [`AlignedBuffer`](crate::util::AlignedBuffer) guarantees.
+    pub fn new(gfn_start: u64, data: &'a [u8], page_type: PageType) -> Result<Self, InputError> {
+        check_pages(data, PAGE_SIZE as usize)?;
+
+        Ok(Self {
+            gfn_start,
+            uaddr: UserPtr::from_ref(data),
+            len: data.len() as _,
+            page_type: page_type.into(),
+            phantom: PhantomData,
+            ..Default::default()
+        })
+    }
+
+    /// The number of bytes which have yet to be processed by the kernel.
+    pub fn remaining(&self) -> u64 {
+        self.len
+    }
+}
+
+/// The ID block, which describes the expected identity of an SEV-SNP guest
+/// and is verified by the firmware at the end of the launch flow.
+///
+/// Corresponds to the ID block structure of the SEV-SNP firmware ABI.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IdBlock {
+    /// The expected launch digest of the guest.
+    pub ld: [u8; 48],
+
+    /// The family ID of the guest, provided by the guest owner.
+    pub family_id: [u8; 16],
+
+    /// The image ID of the guest, provided by the guest owner.
+    pub image_id: [u8; 16],
+
+    /// The version of the ID block format.
+    pub version: u32,
+
+    /// The security version number of the guest.
+    pub guest_svn: u32,
+
+    /// The policy of the guest.
+    pub policy: u64,
+}
+
+assert_layout!(IdBlock, 96, 8);
+
+impl Default for IdBlock {
+    fn default() -> Self {
+        Self {
+            ld: [0; 48],
+            family_id: [0; 16],
+            image_id: [0; 16],
+            version: 1,
+            guest_svn: 0,
+            policy: 0,
+        }
+    }
+}
+
+/// The ID authentication information structure, which carries the public
+/// keys and signatures authenticating an [`IdBlock`].
+///
+/// Corresponds to the ID authentication information structure of the SEV-SNP
+/// firmware ABI. Signatures and public keys are kept in their raw firmware
+/// encodings.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IdAuth {
+    /// The algorithm of the ID key.
+    pub id_key_algo: u32,
+
+    /// The algorithm of the author key.
+    pub auth_key_algo: u32,
+
+    reserved0: [u8; 56],
+
+    /// The signature of the ID block, made with the ID key.
+    pub id_block_sig: [u8; 512],
+
+    /// The public component of the ID key.
+    pub id_key: [u8; 1028],
+
+    reserved1: [u8; 60],
+
+    /// The signature of the ID key, made with the author key.
+    pub id_key_sig: [u8; 512],
+
+    /// The public component of the author key.
+    pub author_key: [u8; 1028],
+
+    reserved2: [u8; 892],
+}
+
+assert_layout!(IdAuth, 4096, 4);
+
+impl Default for IdAuth {
+    fn default() -> Self {
+        Self {
+            id_key_algo: 0,
+            auth_key_algo: 0,
+            reserved0: [0; 56],
+            id_block_sig: [0; 512],
+            id_key: [0; 1028],
+            reserved1: [0; 60],
+            id_key_sig: [0; 512],
+            author_key: [0; 1028],
+            reserved2: [0; 892],
+        }
+    }
+}
+
+/// Complete the launch flow of an SEV-SNP guest.
+///
+/// Corresponds to the kernel struct `kvm_sev_snp_launch_finish`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SnpLaunchFinish<'a> {
+    id_block_uaddr: UserPtr<IdBlock>,
+    id_auth_uaddr: UserPtr<IdAuth>,
+    id_block_en: u8,
+    auth_key_en: u8,
+    vcek_disabled: u8,
+    host_data: [u8; 32],
+    pad0: [u8; 3],
+    flags: u16,
+    pad1: [u64; 4],
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(SnpLaunchFinish<'static>, 88, 8);
+
+impl<'a> SnpLaunchFinish<'a> {
+    /// Create a new `SnpLaunchFinish`, optionally referencing an ID block and
+    /// its authentication information structure.
+    ///
+    /// `auth_key` indicates whether the authentication information contains
+    /// an author key, and `host_data` is reflected verbatim in attestation
+    /// reports.
+    pub fn new(id: Option<(&'a IdBlock, &'a IdAuth)>, auth_key: bool, host_data: [u8; 32]) -> Self {
+        let (id_block_uaddr, id_auth_uaddr) = match id {
+            Some((block, auth)) => (UserPtr::from_ref(block), UserPtr::from_ref(auth)),
+            None => (UserPtr::null(), UserPtr::null()),
+        };
+
+        Self {
+            id_block_uaddr,
+            id_auth_uaddr,
+            id_block_en: id.is_some() as _,
+            auth_key_en: (id.is_some() && auth_key) as _,
+            host_data,
+            ..Default::default()
+        }
+    }
+}
+
+/// Split an optional buffer into the address/length pair used by the kernel,
+/// where an absent buffer is represented by a null address.
+fn uaddr(buf: Option<&[u8]>) -> Result<(UserPtr<[u8]>, u32), InputError> {
+    Ok(match buf {
+        Some(buf) => (UserPtr::from_ref(buf), try_len_u32(buf)?),
+        None => (UserPtr::null(), 0),
+    })
+}
+
+// These values are defined by `enum sev_cmd_id` in the Linux kernel:
+// include/uapi/linux/kvm.h
+impl_const_id! {
+    pub Id: VmId => u32 as CommandCode;
+    Init = 0,
+    EsInit = 1,
+    LaunchStart<'_> = 2,
+    LaunchUpdateData<'_> = 3,
+    LaunchUpdateVmsa = 4,
+    LaunchSecret<'_> = 5,
+    LaunchMeasure<'_> = 6,
+    LaunchFinish = 7,
+    SendStart<'_> = 8,
+    SendUpdateData<'_> = 9,
+    SendUpdateVmsa<'_> = 10,
+    SendFinish = 11,
+    ReceiveStart<'_> = 12,
+    ReceiveUpdateData<'_> = 13,
+    ReceiveFinish = 15,
+    GuestStatus = 16,
+    DbgDecrypt<'_> = 17,
+    DbgEncrypt<'_> = 18,
+    CertExport<'_> = 19,
+    GetAttestationReport<'_> = 20,
+    SendCancel = 21,
+    Init2 = 22,
+    SnpLaunchStart = 100,
+    SnpLaunchUpdate<'_> = 101,
+    SnpLaunchFinish<'_> = 102,
+}
+
+impl_pod! {
+    Init,
+    EsInit,
+    LaunchUpdateVmsa,
+    LaunchFinish,
+    SendFinish,
+    ReceiveFinish,
+    GuestStatus,
+    SendCancel,
+    Init2,
+    SnpLaunchStart,
+}
+
+/// Corresponds to the `KVM_SEV_INIT` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const INIT: Ioctl<WriteRead, &Command<Init>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_ES_INIT` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const ES_INIT: Ioctl<WriteRead, &Command<EsInit>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_INIT2` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const INIT2: Ioctl<WriteRead, &Command<Init2>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_LAUNCH_START` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const LAUNCH_START: Ioctl<WriteRead, &Command<LaunchStart>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_LAUNCH_UPDATE_DATA` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const LAUNCH_UPDATE_DATA: Ioctl<WriteRead, &Command<LaunchUpdateData>> =
+    unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_LAUNCH_UPDATE_VMSA` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const LAUNCH_UPDATE_VMSA: Ioctl<WriteRead, &Command<LaunchUpdateVmsa>> =
+    unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_LAUNCH_SECRET` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const LAUNCH_SECRET: Ioctl<WriteRead, &Command<LaunchSecret>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_LAUNCH_MEASURE` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const LAUNCH_MEASURE: Ioctl<WriteRead, &Command<LaunchMeasure>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_LAUNCH_FINISH` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const LAUNCH_FINISH: Ioctl<WriteRead, &Command<LaunchFinish>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SEND_START` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SEND_START: Ioctl<WriteRead, &Command<SendStart>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SEND_UPDATE_DATA` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SEND_UPDATE_DATA: Ioctl<WriteRead, &Command<SendUpdateData>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SEND_UPDATE_VMSA` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SEND_UPDATE_VMSA: Ioctl<WriteRead, &Command<SendUpdateVmsa>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SEND_FINISH` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SEND_FINISH: Ioctl<WriteRead, &Command<SendFinish>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SEND_CANCEL` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SEND_CANCEL: Ioctl<WriteRead, &Command<SendCancel>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_RECEIVE_START` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const RECEIVE_START: Ioctl<WriteRead, &Command<ReceiveStart>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_RECEIVE_UPDATE_DATA` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const RECEIVE_UPDATE_DATA: Ioctl<WriteRead, &Command<ReceiveUpdateData>> =
+    unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_RECEIVE_FINISH` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const RECEIVE_FINISH: Ioctl<WriteRead, &Command<ReceiveFinish>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_GUEST_STATUS` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const GUEST_STATUS: Ioctl<WriteRead, &Command<GuestStatus>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_DBG_DECRYPT` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const DBG_DECRYPT: Ioctl<WriteRead, &Command<DbgDecrypt>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_DBG_ENCRYPT` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const DBG_ENCRYPT: Ioctl<WriteRead, &Command<DbgEncrypt>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_CERT_EXPORT` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const CERT_EXPORT: Ioctl<WriteRead, &Command<CertExport>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_GET_ATTESTATION_REPORT` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const GET_ATTESTATION_REPORT: Ioctl<WriteRead, &Command<GetAttestationReport>> =
+    unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SNP_LAUNCH_START` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SNP_LAUNCH_START: Ioctl<WriteRead, &Command<SnpLaunchStart>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SNP_LAUNCH_UPDATE` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SNP_LAUNCH_UPDATE: Ioctl<WriteRead, &Command<SnpLaunchUpdate>> = unsafe { ENC_OP.lie() };
+
+/// Corresponds to the `KVM_SEV_SNP_LAUNCH_FINISH` command issued through `KVM_MEMORY_ENCRYPT_OP`.
+pub const SNP_LAUNCH_FINISH: Ioctl<WriteRead, &Command<SnpLaunchFinish>> = unsafe { ENC_OP.lie() };
@@ -8,7 +8,20 @@
 #![allow(clippy::identity_op)]
 #![allow(clippy::unreadable_literal)]
 
+pub mod certs;
+#[cfg(target_arch = "x86_64")]
+pub mod cpuid;
+pub mod device;
 pub mod error;
+pub mod guest;
 pub mod kvm;
+pub mod message;
+#[cfg(feature = "testing")]
+pub mod mock;
+pub mod report;
 pub mod sev;
+pub mod snapshot;
+#[cfg(feature = "bytemuck")]
+pub mod transcript;
 pub mod util;
+pub mod vmsa;
@@ -0,0 +1,490 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// The encrypted messages exchanged between an SEV-SNP guest and the firmware.
+use crate::assert_layout;
+use crate::error::InputError;
+use crate::util::{SwapBytes, TypeLoad, TypeSave};
+
+use std::convert::TryFrom;
+use std::io::{Read, Result, Write};
+
+/// The size of a guest message, header included.
+pub const MESSAGE_SIZE: usize = 4096;
+
+/// The size of the payload of a guest message.
+pub const PAYLOAD_SIZE: usize = MESSAGE_SIZE - MessageHeader::SIZE;
+
+/// The version of the message header defined by the firmware ABI.
+pub const HEADER_VERSION: u8 = 1;
+
+/// The AEAD algorithm with which a guest message is encrypted.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AeadAlgo {
+    /// AES-256-GCM.
+    Aes256Gcm = 1,
+}
+
+impl TryFrom<u8> for AeadAlgo {
+    type Error = u8;
+
+    fn try_from(algo: u8) -> std::result::Result<Self, u8> {
+        match algo {
+            1 => Ok(AeadAlgo::Aes256Gcm),
+            _ => Err(algo),
+        }
+    }
+}
+
+/// The type of a guest message, as defined by the SEV-SNP firmware ABI.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    /// `MSG_CPUID_REQ`
+    CpuidReq = 1,
+
+    /// `MSG_CPUID_RSP`
+    CpuidRsp = 2,
+
+    /// `MSG_KEY_REQ`
+    KeyReq = 3,
+
+    /// `MSG_KEY_RSP`
+    KeyRsp = 4,
+
+    /// `MSG_REPORT_REQ`
+    ReportReq = 5,
+
+    /// `MSG_REPORT_RSP`
+    ReportRsp = 6,
+
+    /// `MSG_EXPORT_REQ`
+    ExportReq = 7,
+
+    /// `MSG_EXPORT_RSP`
+    ExportRsp = 8,
+
+    /// `MSG_IMPORT_REQ`
+    ImportReq = 9,
+
+    /// `MSG_IMPORT_RSP`
+    ImportRsp = 10,
+
+    /// `MSG_ABSORB_REQ`
+    AbsorbReq = 11,
+
+    /// `MSG_ABSORB_RSP`
+    AbsorbRsp = 12,
+
+    /// `MSG_VMRK_REQ`
+    VmrkReq = 13,
+
+    /// `MSG_VMRK_RSP`
+    VmrkRsp = 14,
+
+    /// `MSG_ABSORB_NOMA_REQ`
+    AbsorbNomaReq = 15,
+
+    /// `MSG_ABSORB_NOMA_RSP`
+    AbsorbNomaRsp = 16,
+
+    /// `MSG_TSC_INFO_REQ`
+    TscInfoReq = 17,
+
+    /// `MSG_TSC_INFO_RSP`
+    TscInfoRsp = 18,
+}
+
+impl MessageType {
+    /// The type of the response to a message of this type, if it is a
+    /// request.
+    pub fn response(self) -> Option<MessageType> {
+        let code = self as u8;
+        if code % 2 == 0 {
+            return None;
+        }
+
+        MessageType::try_from(code + 1).ok()
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = u8;
+
+    fn try_from(msg_type: u8) -> std::result::Result<Self, u8> {
+        Ok(match msg_type {
+            1 => MessageType::CpuidReq,
+            2 => MessageType::CpuidRsp,
+            3 => MessageType::KeyReq,
+            4 => MessageType::KeyRsp,
+            5 => MessageType::ReportReq,
+            6 => MessageType::ReportRsp,
+            7 => MessageType::ExportReq,
+            8 => MessageType::ExportRsp,
+            9 => MessageType::ImportReq,
+            10 => MessageType::ImportRsp,
+            11 => MessageType::AbsorbReq,
+            12 => MessageType::AbsorbRsp,
+            13 => MessageType::VmrkReq,
+            14 => MessageType::VmrkRsp,
+            15 => MessageType::AbsorbNomaReq,
+            16 => MessageType::AbsorbNomaRsp,
+            17 => MessageType::TscInfoReq,
+            18 => MessageType::TscInfoRsp,
+            _ => return Err(msg_type),
+        })
+    }
+}
+
+/// The header of a guest message, which is authenticated but not encrypted.
+///
+/// Corresponds to the kernel struct `snp_guest_msg_hdr`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MessageHeader {
+    authtag: [u8; 32],
+    msg_seqno: u64,
+    rsvd1: [u8; 8],
+    algo: u8,
+    hdr_version: u8,
+    hdr_sz: u16,
+    msg_type: u8,
+    msg_version: u8,
+    msg_sz: u16,
+    rsvd2: u32,
+    msg_vmpck: u8,
+    rsvd3: [u8; 35],
+}
+
+assert_layout!(MessageHeader, 96, 8);
+
+// SAFETY: every field is an integer or a byte array, so every bit pattern is
+// valid.
+unsafe impl SwapBytes for MessageHeader {
+    fn swap_bytes(self) -> Self {
+        Self {
+            msg_seqno: self.msg_seqno.swap_bytes(),
+            hdr_sz: self.hdr_sz.swap_bytes(),
+            msg_sz: self.msg_sz.swap_bytes(),
+            rsvd2: self.rsvd2.swap_bytes(),
+            ..self
+        }
+    }
+}
+
+impl MessageHeader {
+    /// The size of the header.
+    pub const SIZE: usize = 96;
+
+    /// The offset of the bytes of the header covered by the authentication
+    /// tag, which start with the algorithm.
+    const AAD_OFFSET: usize = 48;
+
+    /// Create the header of a message of `msg_sz` bytes, encrypted with the
+    /// VM communication key `msg_vmpck` and carrying the sequence number
+    /// `msg_seqno`. The authentication tag is left zeroed.
+    pub fn new(
+        algo: AeadAlgo,
+        msg_type: MessageType,
+        msg_version: u8,
+        msg_sz: u16,
+        msg_seqno: u64,
+        msg_vmpck: u8,
+    ) -> Self {
+        Self {
+            authtag: [0; 32],
+            msg_seqno,
+            rsvd1: [0; 8],
+            algo: algo as _,
+            hdr_version: HEADER_VERSION,
+            hdr_sz: Self::SIZE as _,
+            msg_type: msg_type as _,
+            msg_version,
+            msg_sz,
+            rsvd2: 0,
+            msg_vmpck,
+            rsvd3: [0; 35],
+        }
+    }
+
+    /// Parse a header from its raw bytes.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        bytes.load_le()
+    }
+
+    /// The authentication tag of the message.
+    pub fn authtag(&self) -> &[u8; 32] {
+        &self.authtag
+    }
+
+    /// Set the authentication tag of the message.
+    pub fn set_authtag(&mut self, authtag: [u8; 32]) {
+        self.authtag = authtag;
+    }
+
+    /// The sequence number of the message.
+    pub fn msg_seqno(&self) -> u64 {
+        self.msg_seqno
+    }
+
+    /// The AEAD algorithm the message is encrypted with, or the raw value if
+    /// it is not known.
+    pub fn algo(&self) -> std::result::Result<AeadAlgo, u8> {
+        AeadAlgo::try_from(self.algo)
+    }
+
+    /// The version of the header.
+    pub fn hdr_version(&self) -> u8 {
+        self.hdr_version
+    }
+
+    /// The size of the header.
+    pub fn hdr_size(&self) -> usize {
+        self.hdr_sz as _
+    }
+
+    /// The type of the message, or the raw value if it is not known.
+    pub fn msg_type(&self) -> std::result::Result<MessageType, u8> {
+        MessageType::try_from(self.msg_type)
+    }
+
+    /// The version of the message.
+    pub fn msg_version(&self) -> u8 {
+        self.msg_version
+    }
+
+    /// The size of the message payload.
+    pub fn msg_size(&self) -> usize {
+        self.msg_sz as _
+    }
+
+    /// The index of the VM communication key the message is encrypted with,
+    /// which is also the VMPL of the sender.
+    pub fn msg_vmpck(&self) -> u8 {
+        self.msg_vmpck
+    }
+
+    /// The initialization vector of the message, which is its sequence number
+    /// in little endian.
+    pub fn iv(&self) -> [u8; 12] {
+        let mut iv = [0; 12];
+        iv[..8].copy_from_slice(&self.msg_seqno.to_le_bytes());
+        iv
+    }
+
+    /// The additional authenticated data of the message: the bytes of the
+    /// header from the algorithm onwards.
+    pub fn aad(&self) -> [u8; Self::SIZE - Self::AAD_OFFSET] {
+        let mut aad = [0; Self::SIZE - Self::AAD_OFFSET];
+        aad[0] = self.algo;
+        aad[1] = self.hdr_version;
+        aad[2..4].copy_from_slice(&self.hdr_sz.to_le_bytes());
+        aad[4] = self.msg_type;
+        aad[5] = self.msg_version;
+        aad[6..8].copy_from_slice(&self.msg_sz.to_le_bytes());
+        aad[8..12].copy_from_slice(&self.rsvd2.to_le_bytes());
+        aad[12] = self.msg_vmpck;
+        aad[13..].copy_from_slice(&self.rsvd3);
+        aad
+    }
+}
+
+/// A guest message: a header followed by the encrypted payload.
+///
+/// Corresponds to the kernel struct `snp_guest_msg`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GuestMessage {
+    hdr: MessageHeader,
+    payload: [u8; PAYLOAD_SIZE],
+}
+
+assert_layout!(GuestMessage, MESSAGE_SIZE, 8);
+
+// SAFETY: the payload is a byte array, so every bit pattern is valid.
+unsafe impl SwapBytes for GuestMessage {
+    fn swap_bytes(self) -> Self {
+        Self {
+            hdr: self.hdr.swap_bytes(),
+            ..self
+        }
+    }
+}
+
+impl GuestMessage {
+    /// Create a message carrying the encrypted `payload`, whose size is
+    /// recorded in the header.
+    pub fn new(mut hdr: MessageHeader, payload: &[u8]) -> std::result::Result<Self, InputError> {
+        if payload.len() > PAYLOAD_SIZE {
+            return Err(InputError::TooLong {
+                len: payload.len(),
+                max: PAYLOAD_SIZE,
+            });
+        }
+
+        hdr.msg_sz = payload.len() as _;
+        let mut msg = Self {
+            hdr,
+            payload: [0; PAYLOAD_SIZE],
+        };
+        msg.payload[..payload.len()].copy_from_slice(payload);
+        Ok(msg)
+    }
+
+    /// Parse a message from its raw bytes.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        bytes.load_le()
+    }
+
+    /// The header of the message.
+    pub fn header(&self) -> &MessageHeader {
+        &self.hdr
+    }
+
+    /// The header of the message, such as for setting its authentication tag.
+    pub fn header_mut(&mut self) -> &mut MessageHeader {
+        &mut self.hdr
+    }
+
+    /// The encrypted payload, as long as recorded in the header.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.hdr.msg_size().min(PAYLOAD_SIZE)]
+    }
+
+    /// The encrypted payload, as long as recorded in the header, for
+    /// encrypting or decrypting it in place.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let len = self.hdr.msg_size().min(PAYLOAD_SIZE);
+        &mut self.payload[..len]
+    }
+}
+
+/// The sequence numbers of the messages exchanged with the firmware through
+/// one VM communication key.
+///
+/// Every message is encrypted with its sequence number as IV, so a sequence
+/// number must never be used twice with the same key. The firmware expects
+/// each request to carry the number following the last response, and answers
+/// with the number following the request. This type hands out every number
+/// at most once, which is why it is neither `Clone` nor `Copy`; to survive a
+/// restart, the counter must be persisted with [`GuestMessageSeq::save`] once
+/// a request has been sent and restored with [`GuestMessageSeq::load`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct GuestMessageSeq {
+    last: u64,
+}
+
+impl GuestMessageSeq {
+    /// The highest sequence number a message may carry, since the GHCB
+    /// specification stores it in 32 bits.
+    pub const MAX: u64 = u32::MAX as u64;
+
+    /// The counter of a VM communication key which has never been used.
+    pub fn new() -> Self {
+        Self { last: 0 }
+    }
+
+    /// Resume the counter after the response with sequence number `last`.
+    pub fn restore(last: u64) -> Self {
+        Self { last }
+    }
+
+    /// The sequence number of the last response, or of the response expected
+    /// to the last request, which is the value to persist.
+    pub fn last(&self) -> u64 {
+        self.last
+    }
+
+    /// Reserve the sequence number of the next request, along with that of
+    /// its response, or `None` once the sequence numbers are exhausted and
+    /// the key must no longer be used.
+    ///
+    /// The numbers are reserved even if the request is never sent, since it
+    /// is not known whether the firmware has seen them.
+    pub fn next_request(&mut self) -> Option<u64> {
+        let request = self.last.checked_add(1)?;
+        if request >= Self::MAX {
+            return None;
+        }
+
+        self.last = request + 1;
+        Some(request)
+    }
+
+    /// Whether `hdr` is the header of the response to the last request.
+    pub fn is_response(&self, hdr: &MessageHeader) -> bool {
+        self.last != 0 && hdr.msg_seqno() == self.last
+    }
+
+    /// Read a persisted counter.
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self::restore(reader.load_le()?))
+    }
+
+    /// Persist the counter.
+    pub fn save(&self, writer: &mut impl Write) -> Result<()> {
+        writer.save_le(&self.last)
+    }
+}
+
+impl Default for GuestMessageSeq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(msg_seqno: u64) -> MessageHeader {
+        MessageHeader::new(
+            AeadAlgo::Aes256Gcm,
+            MessageType::KeyRsp,
+            1,
+            64,
+            msg_seqno,
+            0,
+        )
+    }
+
+    #[test]
+    fn seq_requests_and_responses() {
+        let mut seq = GuestMessageSeq::new();
+        assert!(!seq.is_response(&response(0)));
+
+        assert_eq!(seq.next_request(), Some(1));
+        assert!(seq.is_response(&response(2)));
+        assert!(!seq.is_response(&response(1)));
+        assert!(!seq.is_response(&response(3)));
+
+        assert_eq!(seq.next_request(), Some(3));
+        assert_eq!(seq.last(), 4);
+        assert!(seq.is_response(&response(4)));
+    }
+
+    #[test]
+    fn seq_exhaustion() {
+        let mut seq = GuestMessageSeq::restore(GuestMessageSeq::MAX - 3);
+        assert_eq!(seq.next_request(), Some(GuestMessageSeq::MAX - 2));
+        assert_eq!(seq.last(), GuestMessageSeq::MAX - 1);
+
+        // The response to the next request would need a number above `MAX`.
+        assert_eq!(seq.next_request(), None);
+        assert_eq!(seq.next_request(), None);
+        assert_eq!(seq.last(), GuestMessageSeq::MAX - 1);
+
+        assert_eq!(GuestMessageSeq::restore(u64::MAX).next_request(), None);
+    }
+
+    #[test]
+    fn seq_save_and_load() {
+        let mut seq = GuestMessageSeq::new();
+        seq.next_request();
+
+        let mut saved = Vec::new();
+        seq.save(&mut saved).unwrap();
+        assert_eq!(saved, 2u64.to_le_bytes());
+        assert_eq!(GuestMessageSeq::load(&mut &saved[..]).unwrap(), seq);
+    }
+}
@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// A fake device for exercising SEV code on machines without SEV hardware.
+use crate::device::SevDevice;
+use crate::error::CommandError;
+use crate::sev::Id;
+use crate::transcript::Transcript;
+
+use bytemuck::{NoUninit, Pod};
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+/// The `errno` returned by the kernel when the firmware fails a command.
+const EIO: i32 = 5;
+
+/// A canned outcome of a command issued to a [`MockDevice`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MockResponse {
+    /// The command succeeds, and these bytes are copied over its payload, as
+    /// if written by the firmware.
+    Payload(Vec<u8>),
+
+    /// The command fails with this firmware status code.
+    Status(u32),
+
+    /// The command fails in the kernel with this `errno`, without reaching
+    /// the firmware.
+    Os(i32),
+}
+
+/// A device which answers commands with responses programmed per command ID,
+/// rather than issuing them to the hardware.
+///
+/// Responses programmed for an ID are used up in order by the commands issued
+/// with that ID. A command for which no response remains succeeds, leaving
+/// its payload untouched.
+///
+/// Since a payload response overwrites the command, only commands which are
+/// valid for any bytes can be issued, that is plain data without references
+/// to buffers of the caller.
+#[derive(Clone, Debug, Default)]
+pub struct MockDevice {
+    responses: HashMap<u32, VecDeque<MockResponse>>,
+    issued: Vec<u32>,
+}
+
+impl MockDevice {
+    /// Create a device with no programmed responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Program the next response to a command with the given ID.
+    pub fn respond(&mut self, id: u32, response: MockResponse) -> &mut Self {
+        self.responses.entry(id).or_default().push_back(response);
+        self
+    }
+
+    /// Program the next command of type `T` to succeed with `payload` as its
+    /// result.
+    pub fn respond_with<T: Id + NoUninit>(&mut self, payload: &T) -> &mut Self {
+        let bytes = bytemuck::bytes_of(payload);
+        self.respond(T::ID, MockResponse::Payload(bytes.to_vec()))
+    }
+
+    /// Program the next command with the given ID to fail with the firmware
+    /// status code `status`.
+    pub fn fail(&mut self, id: u32, status: u32) -> &mut Self {
+        self.respond(id, MockResponse::Status(status))
+    }
+
+    /// Create a device which answers commands as recorded in `transcript`, so
+    /// that replaying the same sequence of commands yields the same results.
+    pub fn replay(transcript: &Transcript) -> Self {
+        let mut device = Self::new();
+
+        for entry in transcript.entries() {
+            let response = match entry.errno {
+                None => MockResponse::Payload(entry.output.clone()),
+                Some(_) if entry.status != 0 => MockResponse::Status(entry.status),
+                Some(errno) => MockResponse::Os(errno),
+            };
+            device.respond(entry.id, response);
+        }
+
+        device
+    }
+
+    /// The IDs of the commands issued so far, in order.
+    pub fn issued(&self) -> &[u32] {
+        &self.issued
+    }
+}
+
+/// The error of a command of type `T` which the kernel failed with `errno`,
+/// along with the firmware status code `status`.
+fn failure<T: Id>(errno: i32, status: u32) -> CommandError {
+    CommandError::new(io::Error::from_raw_os_error(errno), status).with_command(T::ID, None)
+}
+
+/// Carry out a programmed response to a command.
+///
+/// A payload response is copied over the command, truncated to the size of
+/// the command.
+fn answer<T: Id + Pod>(subcmd: &mut T, response: MockResponse) -> Result<(), CommandError> {
+    match response {
+        MockResponse::Payload(bytes) => {
+            let payload = bytemuck::bytes_of_mut(subcmd);
+            let len = bytes.len().min(payload.len());
+            payload[..len].copy_from_slice(&bytes[..len]);
+            Ok(())
+        }
+        MockResponse::Status(status) => Err(failure::<T>(EIO, status)),
+        MockResponse::Os(errno) => Err(failure::<T>(errno, 0)),
+    }
+}
+
+impl<T: Id + Pod> SevDevice<T> for MockDevice {
+    type Error = CommandError;
+
+    /// Answer the command with the next response programmed for its ID.
+    fn issue_ioctl(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        self.issued.push(T::ID);
+
+        match self.responses.get_mut(&T::ID).and_then(VecDeque::pop_front) {
+            Some(response) => answer(subcmd, response),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Fault {
+    id: u32,
+    first: usize,
+    times: usize,
+    errno: i32,
+    status: u32,
+}
+
+/// A device which makes chosen commands issued through it fail, while passing
+/// the others on to the device it wraps, for testing retry and rollback logic.
+///
+/// Faults are described by the occurrences of the commands with a given ID
+/// they apply to, counting from one: for example, a fault of the third
+/// `LAUNCH_UPDATE_DATA` command, or of the first two `PLATFORM_STATUS`
+/// commands after which the device recovers.
+#[derive(Clone, Debug)]
+pub struct FaultInjector<D> {
+    device: D,
+    faults: Vec<Fault>,
+    counts: HashMap<u32, usize>,
+}
+
+impl<D> FaultInjector<D> {
+    /// Inject faults into the commands issued to `device`.
+    pub fn new(device: D) -> Self {
+        Self {
+            device,
+            faults: Vec::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Fail `times` consecutive commands with the given ID with the firmware
+    /// status code `status` instead of issuing them, starting with the
+    /// `first` such command.
+    pub fn inject(&mut self, id: u32, first: usize, times: usize, status: u32) -> &mut Self {
+        self.faults.push(Fault {
+            id,
+            first,
+            times,
+            errno: EIO,
+            status,
+        });
+        self
+    }
+
+    /// Fail `times` consecutive commands with the given ID in the kernel
+    /// with `errno` instead of issuing them, starting with the `first` such
+    /// command.
+    pub fn inject_os(&mut self, id: u32, first: usize, times: usize, errno: i32) -> &mut Self {
+        self.faults.push(Fault {
+            id,
+            first,
+            times,
+            errno,
+            status: 0,
+        });
+        self
+    }
+
+    /// Fail the `nth` command with the given ID with the firmware status
+    /// code `status`.
+    pub fn fail_nth(&mut self, id: u32, nth: usize, status: u32) -> &mut Self {
+        self.inject(id, nth, 1, status)
+    }
+
+    /// Give back the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: SevDevice<T, Error = CommandError>, T: Id> SevDevice<T> for FaultInjector<D> {
+    type Error = CommandError;
+
+    fn issue_ioctl(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        let count = self.counts.entry(T::ID).or_default();
+        *count += 1;
+
+        let n = *count;
+        let fault = self
+            .faults
+            .iter()
+            .find(|f| f.id == T::ID && n >= f.first && n - f.first < f.times);
+
+        match fault {
+            Some(fault) => Err(failure::<T>(fault.errno, fault.status)),
+            None => self.device.issue_ioctl(subcmd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{codes, Error};
+    use crate::kvm::{GuestStatus, LaunchFinish};
+    use crate::sev::PlatformStatus;
+
+    #[test]
+    fn programmed_payloads() {
+        let mut expected = GuestStatus::default();
+        bytemuck::bytes_of_mut(&mut expected)[0] = 7;
+
+        let mut device = MockDevice::new();
+        device
+            .respond_with(&expected)
+            .respond(GuestStatus::ID, MockResponse::Payload(vec![9, 0]));
+
+        let mut status = GuestStatus::default();
+        device.issue_ioctl(&mut status).unwrap();
+        assert_eq!(status, expected);
+        assert_eq!(status.handle(), 7);
+
+        // A short payload only overwrites the start of the command.
+        device.issue_ioctl(&mut status).unwrap();
+        assert_eq!(status.handle(), 9);
+
+        // Once the responses are used up, commands succeed untouched.
+        device.issue_ioctl(&mut status).unwrap();
+        assert_eq!(status.handle(), 9);
+    }
+
+    #[test]
+    fn issued_commands() {
+        let mut device = MockDevice::new();
+        device.issue_ioctl(&mut PlatformStatus::default()).unwrap();
+        device.issue_ioctl(&mut LaunchFinish).unwrap();
+        device.issue_ioctl(&mut PlatformStatus::default()).unwrap();
+
+        let expected = [PlatformStatus::ID, LaunchFinish::ID, PlatformStatus::ID];
+        assert_eq!(device.issued(), expected);
+    }
+
+    #[test]
+    fn failures_become_command_errors() {
+        let mut device = MockDevice::new();
+        device
+            .fail(LaunchFinish::ID, codes::SEV_RET_INVALID_GUEST)
+            .respond(LaunchFinish::ID, MockResponse::Os(16));
+
+        let err = device.issue_ioctl(&mut LaunchFinish).unwrap_err();
+        assert_eq!(err.status(), codes::SEV_RET_INVALID_GUEST);
+        assert!(matches!(err.firmware_error(), Some(Error::InvalidGuest)));
+        assert_eq!(err.os_error().raw_os_error(), Some(EIO));
+        assert_eq!(err.command_code(), Some(LaunchFinish::ID));
+
+        let err = device.issue_ioctl(&mut LaunchFinish).unwrap_err();
+        assert_eq!(err.status(), 0);
+        assert!(err.firmware_error().is_none());
+        assert_eq!(err.os_error().raw_os_error(), Some(16));
+
+        device.issue_ioctl(&mut LaunchFinish).unwrap();
+    }
+
+    #[test]
+    fn fault_window() {
+        let mut faults = FaultInjector::new(MockDevice::new());
+        faults
+            .inject(LaunchFinish::ID, 2, 2, codes::SEV_RET_INVALID_GUEST)
+            .inject_os(LaunchFinish::ID, 5, 1, 16)
+            .fail_nth(PlatformStatus::ID, 1, codes::SEV_RET_INVALID_GUEST);
+
+        let outcomes: Vec<_> = (0..6)
+            .map(|_| match faults.issue_ioctl(&mut LaunchFinish) {
+                Ok(()) => None,
+                Err(e) => Some((e.os_error().raw_os_error(), e.status())),
+            })
+            .collect();
+
+        let fw = Some((Some(EIO), codes::SEV_RET_INVALID_GUEST));
+        assert_eq!(outcomes, [None, fw, fw, None, Some((Some(16), 0)), None]);
+
+        // Commands with other IDs are counted separately.
+        assert!(faults.issue_ioctl(&mut PlatformStatus::default()).is_err());
+        assert!(faults.issue_ioctl(&mut PlatformStatus::default()).is_ok());
+
+        // Only the commands which were not failed reached the device.
+        let reached = [
+            LaunchFinish::ID,
+            LaunchFinish::ID,
+            LaunchFinish::ID,
+            PlatformStatus::ID,
+        ];
+        assert_eq!(faults.into_inner().issued(), reached);
+    }
+}
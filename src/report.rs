@@ -0,0 +1,407 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// The attestation report produced by the SEV-SNP firmware.
+use crate::assert_layout;
+use crate::sev::{ChipId, GuestPolicy, TcbVersion, Version};
+use crate::util::{ByteArray, SwapBytes, TypeLoad, TypeSave};
+
+use bitflags::bitflags;
+
+use std::io::Result;
+
+/// An SEV-SNP attestation report, as defined by the `ATTESTATION_REPORT`
+/// structure of the SEV-SNP firmware ABI.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AttestationReport {
+    version: u32,
+    guest_svn: u32,
+    policy: u64,
+    family_id: [u8; 16],
+    image_id: [u8; 16],
+    vmpl: u32,
+    sig_algo: u32,
+    current_tcb: u64,
+    plat_info: u64,
+    key_info: u32,
+    reserved0: u32,
+    report_data: ByteArray<64>,
+    measurement: ByteArray<48>,
+    host_data: [u8; 32],
+    id_key_digest: [u8; 48],
+    author_key_digest: [u8; 48],
+    report_id: [u8; 32],
+    report_id_ma: [u8; 32],
+    reported_tcb: u64,
+    reserved1: [u8; 24],
+    chip_id: ChipId,
+    committed_tcb: u64,
+    current_build: u8,
+    current_minor: u8,
+    current_major: u8,
+    reserved2: u8,
+    committed_build: u8,
+    committed_minor: u8,
+    committed_major: u8,
+    reserved3: u8,
+    launch_tcb: u64,
+    reserved4: [u8; 168],
+    signature: EcdsaP384Signature,
+}
+
+assert_layout!(AttestationReport, 0x4A0, 8);
+
+// SAFETY: the report is `repr(C)` without padding and consists only of
+// integers and byte arrays, so every bit pattern is valid.
+#[cfg(feature = "bytemuck")]
+unsafe impl bytemuck::Zeroable for AttestationReport {}
+
+#[cfg(feature = "bytemuck")]
+unsafe impl bytemuck::Pod for AttestationReport {}
+
+// SAFETY: every field is an integer or a byte array, so every bit pattern is
+// valid.
+unsafe impl SwapBytes for AttestationReport {
+    fn swap_bytes(self) -> Self {
+        Self {
+            version: self.version.swap_bytes(),
+            guest_svn: self.guest_svn.swap_bytes(),
+            policy: self.policy.swap_bytes(),
+            vmpl: self.vmpl.swap_bytes(),
+            sig_algo: self.sig_algo.swap_bytes(),
+            current_tcb: self.current_tcb.swap_bytes(),
+            plat_info: self.plat_info.swap_bytes(),
+            key_info: self.key_info.swap_bytes(),
+            reported_tcb: self.reported_tcb.swap_bytes(),
+            committed_tcb: self.committed_tcb.swap_bytes(),
+            launch_tcb: self.launch_tcb.swap_bytes(),
+            ..self
+        }
+    }
+}
+
+impl AttestationReport {
+    /// The number of bytes at the start of the report covered by its
+    /// signature.
+    pub const SIGNED_LEN: usize = 0x2A0;
+
+    /// Parse an attestation report from its raw bytes, such as those returned
+    /// by [`ReportResp::report`](crate::guest::ReportResp::report).
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        bytes.load_le()
+    }
+
+    /// The version of the report format.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The security version number of the guest.
+    pub fn guest_svn(&self) -> u32 {
+        self.guest_svn
+    }
+
+    /// The policy the guest was launched with.
+    pub fn policy(&self) -> GuestPolicy {
+        GuestPolicy::from_raw(self.policy)
+    }
+
+    /// The family ID provided at launch.
+    pub fn family_id(&self) -> &[u8; 16] {
+        &self.family_id
+    }
+
+    /// The image ID provided at launch.
+    pub fn image_id(&self) -> &[u8; 16] {
+        &self.image_id
+    }
+
+    /// The VMPL of the request for this report.
+    pub fn vmpl(&self) -> u32 {
+        self.vmpl
+    }
+
+    /// The algorithm used to sign this report.
+    pub fn sig_algo(&self) -> u32 {
+        self.sig_algo
+    }
+
+    /// The current TCB version of the platform.
+    pub fn current_tcb(&self) -> TcbVersion {
+        self.current_tcb.into()
+    }
+
+    /// Information about the platform.
+    pub fn plat_info(&self) -> PlatformInfo {
+        PlatformInfo::from_bits_truncate(self.plat_info)
+    }
+
+    /// Information about the key used to sign this report.
+    pub fn key_info(&self) -> KeyInfo {
+        KeyInfo(self.key_info)
+    }
+
+    /// The data provided by the guest in the request for this report.
+    pub fn report_data(&self) -> &ByteArray<64> {
+        &self.report_data
+    }
+
+    /// The launch measurement of the guest.
+    pub fn measurement(&self) -> &ByteArray<48> {
+        &self.measurement
+    }
+
+    /// The data provided by the hypervisor at launch.
+    pub fn host_data(&self) -> &[u8; 32] {
+        &self.host_data
+    }
+
+    /// The digest of the ID key which signed the ID block provided at launch.
+    pub fn id_key_digest(&self) -> &[u8; 48] {
+        &self.id_key_digest
+    }
+
+    /// The digest of the author key which signed the ID key, if any.
+    pub fn author_key_digest(&self) -> &[u8; 48] {
+        &self.author_key_digest
+    }
+
+    /// The report ID of the guest.
+    pub fn report_id(&self) -> &[u8; 32] {
+        &self.report_id
+    }
+
+    /// The report ID of the guest's migration agent.
+    pub fn report_id_ma(&self) -> &[u8; 32] {
+        &self.report_id_ma
+    }
+
+    /// The TCB version used to derive the key which signed this report.
+    pub fn reported_tcb(&self) -> TcbVersion {
+        self.reported_tcb.into()
+    }
+
+    /// The identifier unique to the chip, unless masked by the platform.
+    pub fn chip_id(&self) -> &ChipId {
+        &self.chip_id
+    }
+
+    /// The committed TCB version of the platform.
+    pub fn committed_tcb(&self) -> TcbVersion {
+        self.committed_tcb.into()
+    }
+
+    /// The version of the firmware currently running.
+    pub fn current_version(&self) -> Version {
+        Version {
+            major: self.current_major,
+            minor: self.current_minor,
+        }
+    }
+
+    /// The build number of the firmware currently running.
+    pub fn current_build(&self) -> u8 {
+        self.current_build
+    }
+
+    /// The version of the committed firmware.
+    pub fn committed_version(&self) -> Version {
+        Version {
+            major: self.committed_major,
+            minor: self.committed_minor,
+        }
+    }
+
+    /// The build number of the committed firmware.
+    pub fn committed_build(&self) -> u8 {
+        self.committed_build
+    }
+
+    /// The current TCB version of the platform when the guest was launched.
+    pub fn launch_tcb(&self) -> TcbVersion {
+        self.launch_tcb.into()
+    }
+
+    /// The signature over bytes 0x0 to 0x29F of this report, which are
+    /// returned by [`AttestationReport::signed_bytes`].
+    pub fn signature(&self) -> &EcdsaP384Signature {
+        &self.signature
+    }
+
+    /// The bytes of this report covered by its signature, in the order the
+    /// firmware signed them.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(std::mem::size_of::<Self>());
+        bytes
+            .save_le(self)
+            .expect("writing to a vector cannot fail");
+        bytes.truncate(Self::SIGNED_LEN);
+        bytes
+    }
+}
+
+bitflags! {
+    /// The configuration of the platform a report was generated on, as
+    /// defined by the `PLATFORM_INFO` field of the report.
+    #[derive(Default)]
+    pub struct PlatformInfo: u64 {
+        /// Simultaneous multithreading is enabled.
+        const SMT_EN = 1 << 0;
+
+        /// Transparent secure memory encryption is enabled.
+        const TSME_EN = 1 << 1;
+
+        /// The platform is using error correcting codes for memory.
+        const ECC_EN = 1 << 2;
+
+        /// The Running Average Power Limit is disabled.
+        const RAPL_DIS = 1 << 3;
+
+        /// Ciphertext hiding is enabled for the DRAM.
+        const CIPHERTEXT_HIDING_EN = 1 << 4;
+
+        /// The check for aliased memory completed since the last reset.
+        const ALIAS_CHECK_COMPLETE = 1 << 5;
+    }
+}
+
+/// The key which signed a report.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SigningKey {
+    /// The versioned chip endorsement key.
+    Vcek,
+
+    /// The versioned loaded endorsement key.
+    Vlek,
+
+    /// The report is not signed.
+    None,
+
+    /// A reserved value, unknown to this crate.
+    Reserved(u8),
+}
+
+/// Information about the key which signed a report, as defined by the
+/// `KEY_INFO` field of the report.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyInfo(u32);
+
+impl KeyInfo {
+    /// Wrap the raw `KEY_INFO` field.
+    pub fn new(key_info: u32) -> Self {
+        Self(key_info)
+    }
+
+    /// Whether the digest of the author key is included in the report.
+    pub fn author_key_en(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+
+    /// Whether the chip ID and the keys derived from it are masked.
+    pub fn mask_chip_key(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    /// The key which signed the report.
+    pub fn signing_key(&self) -> SigningKey {
+        match (self.0 >> 2) & 0x7 {
+            0 => SigningKey::Vcek,
+            1 => SigningKey::Vlek,
+            7 => SigningKey::None,
+            key => SigningKey::Reserved(key as _),
+        }
+    }
+
+    /// The raw `KEY_INFO` field.
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+/// An ECDSA P-384 signature, as defined by the `SIGNATURE` structure of the
+/// SEV-SNP firmware ABI.
+///
+/// Both components are stored in little endian and zero-extended to 72 bytes;
+/// most cryptography libraries expect them in big endian instead, as returned
+/// by [`EcdsaP384Signature::r_be`] and [`EcdsaP384Signature::s_be`].
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct EcdsaP384Signature {
+    r: [u8; 72],
+    s: [u8; 72],
+    reserved: [u8; 368],
+}
+
+assert_layout!(EcdsaP384Signature, 512, 1);
+
+// SAFETY: the signature consists only of byte arrays.
+unsafe impl SwapBytes for EcdsaP384Signature {
+    fn swap_bytes(self) -> Self {
+        self
+    }
+}
+
+impl EcdsaP384Signature {
+    /// The size of each component of a P-384 signature.
+    pub const COMPONENT_LEN: usize = 48;
+
+    /// Create a signature from its components in little endian.
+    pub fn new(r: [u8; 72], s: [u8; 72]) -> Self {
+        Self {
+            r,
+            s,
+            reserved: [0; 368],
+        }
+    }
+
+    /// The R component, in little endian.
+    pub fn r(&self) -> &[u8; 72] {
+        &self.r
+    }
+
+    /// The S component, in little endian.
+    pub fn s(&self) -> &[u8; 72] {
+        &self.s
+    }
+
+    /// The R component, in big endian.
+    pub fn r_be(&self) -> [u8; 48] {
+        Self::to_be(&self.r)
+    }
+
+    /// The S component, in big endian.
+    pub fn s_be(&self) -> [u8; 48] {
+        Self::to_be(&self.s)
+    }
+
+    /// The signature as the concatenation of R and S in big endian, the
+    /// fixed-size encoding accepted by most ECDSA implementations.
+    pub fn to_be_bytes(&self) -> [u8; 96] {
+        let mut bytes = [0; 96];
+        bytes[..48].copy_from_slice(&self.r_be());
+        bytes[48..].copy_from_slice(&self.s_be());
+        bytes
+    }
+
+    fn to_be(component: &[u8; 72]) -> [u8; 48] {
+        let mut be = [0; 48];
+        be.copy_from_slice(&component[..Self::COMPONENT_LEN]);
+        be.reverse();
+        be
+    }
+}
+
+impl Default for EcdsaP384Signature {
+    fn default() -> Self {
+        Self::new([0; 72], [0; 72])
+    }
+}
+
+impl std::fmt::Debug for EcdsaP384Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EcdsaP384Signature")
+            .field("r", &format_args!("{}", ByteArray(self.r_be())))
+            .field("s", &format_args!("{}", ByteArray(self.s_be())))
+            .finish()
+    }
+}
@@ -1,24 +1,76 @@
 // SPDX-License-Identifier: Apache-2.0
 
 /// Helpful abstractions for issuing ioctls to the SEV platform.
-use crate::error::{Error, Indeterminate};
+use crate::device::{DeviceOptions, SevDevice};
+use crate::error::{CommandError, Error, Indeterminate, InputError};
+use crate::util::{impl_pod, probe, try_len_u32, ByteArray, SwapBytes, UserPtr};
+use crate::{assert_layout, impl_const_id};
 
+use bitflags::bitflags;
 use iocuddle::*;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+use std::convert::TryFrom;
+use std::io;
 use std::marker::PhantomData;
+use std::mem::size_of;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 /// The SEV iocuddle group.
 pub const SEV: Group = Group::new(b'S');
 
 /// An ID to be associated with an SEV ioctl.
+///
+/// Commands issued through `/dev/sev` and through KVM share this trait, but
+/// their IDs overlap, so devices accept only [`PlatformId`] or
+/// [`crate::kvm::VmId`] commands.
 pub trait Id {
     /// The value of the ID (defined in the linux kernel).
     const ID: u32;
 }
 
+/// The ID of a command issued to the platform through `/dev/sev`.
+pub trait PlatformId: Id {}
+
+/// The behavior shared by every command passed to the SEV firmware, whether
+/// it is issued through `/dev/sev` or through KVM.
+pub trait FirmwareCommand {
+    /// The subcommand carried by this command.
+    type Subcommand: Id;
+
+    /// The status code reported by the firmware once the command has been
+    /// issued, where zero means the firmware did not report an error.
+    fn status(&self) -> u32;
+
+    /// The ID of the subcommand carried by this command.
+    fn code(&self) -> u32 {
+        Self::Subcommand::ID
+    }
+
+    /// The name of the subcommand carried by this command, for use in
+    /// messages.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self::Subcommand>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Pair the error returned by the ioctl with the status reported by the
+    /// firmware and the identity of the command, so that none of them is lost.
+    fn error(&self, err: std::io::Error) -> CommandError {
+        CommandError::new(err, self.status()).with_command(self.code(), Some(self.name()))
+    }
+
+    /// Rather than relying on status codes from the Linux kernel, match the specific error code
+    /// returned by the SEV firmware to output errors in more detail.
+    fn encapsulate(&self, err: std::io::Error) -> Indeterminate<Error> {
+        self.error(err).into()
+    }
+}
+
 /// The Rust-flavored, FFI-friendly version of `struct sev_issue_cmd` which is
 /// used to pass arguments to the SEV ioctl implementation.
 ///
@@ -26,11 +78,13 @@ pub trait Id {
 #[repr(C, packed)]
 pub struct Command<'a, T: Id> {
     code: u32,
-    data: u64,
+    data: UserPtr<T>,
     error: u32,
     phantom: PhantomData<&'a T>,
 }
 
+assert_layout!(Command<'static, PekGen>, 16, 1);
+
 impl<'a, T: Id> Command<'a, T> {
     /// Create an SEV-SNP command with the expectation that the host platform/kernel will write to
     /// the caller's address space either to the data held in the `Command.subcmd` field or some
@@ -38,7 +92,7 @@ impl<'a, T: Id> Command<'a, T> {
     pub fn from_mut(subcmd: &'a mut T) -> Self {
         Command {
             code: T::ID,
-            data: subcmd as *mut T as u64,
+            data: UserPtr::from_mut(subcmd),
             error: 0,
             phantom: PhantomData,
         }
@@ -51,22 +105,349 @@ impl<'a, T: Id> Command<'a, T> {
     pub fn from(subcmd: &'a T) -> Self {
         Command {
             code: T::ID,
-            data: subcmd as *const T as u64,
+            data: UserPtr::from_ref(subcmd),
             error: 0,
             phantom: PhantomData,
         }
     }
+}
 
-    /// Rather than relying on status codes from the Linux kernel, match the specific error code
-    /// returned by the SNP firmware to output errors in more detail.
-    pub fn encapsulate(&self, err: std::io::Error) -> Indeterminate<Error> {
-        match self.error {
-            0 => Indeterminate::<Error>::from(err),
-            _ => Indeterminate::<Error>::from(self.error as u32),
+impl<T: Id> FirmwareCommand for Command<'_, T> {
+    type Subcommand = T;
+
+    fn status(&self) -> u32 {
+        self.error
+    }
+}
+
+/// The SEV platform firmware, reached through the `/dev/sev` device.
+#[derive(Debug)]
+pub struct Firmware(OwnedFd);
+
+impl Firmware {
+    /// Open the SEV platform firmware device.
+    pub fn open() -> io::Result<Firmware> {
+        Self::open_with(&DeviceOptions::new())
+    }
+
+    /// Open the SEV platform firmware device with the given options, such as
+    /// another path than `/dev/sev`.
+    pub fn open_with(options: &DeviceOptions) -> io::Result<Firmware> {
+        options.open("/dev/sev").map(|file| Firmware(file.into()))
+    }
+
+    /// Issue a command to the firmware through the `SEV_ISSUE_CMD` ioctl,
+    /// reporting a failure along with the status reported by the firmware.
+    pub fn issue<T: PlatformId>(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        let issue_cmd: Ioctl<WriteRead, &Command<T>> = unsafe { SEV.write_read(0) };
+        let mut cmd = Command::from_mut(subcmd);
+        issue_cmd
+            .ioctl(&mut self.0, &mut cmd)
+            .map_err(|e| cmd.error(e))?;
+        Ok(())
+    }
+
+    /// Query the state of the platform and the version of its firmware.
+    pub fn platform_status(&mut self) -> Result<Status, Indeterminate<Error>> {
+        let mut raw = PlatformStatus::default();
+        self.issue(&mut raw)?;
+
+        Ok(Status {
+            version: Version {
+                major: raw.api_major,
+                minor: raw.api_minor,
+            },
+            build: raw.build,
+            state: PlatformState::try_from(raw.state).map_err(|_| Indeterminate::Unknown)?,
+            guests: raw.guest_count,
+            flags: PlatformStatusFlags::from_bits_truncate(raw.flags),
+        })
+    }
+
+    /// Query the state of the platform as it relates to SEV-SNP.
+    pub fn snp_platform_status(&mut self) -> Result<SnpPlatformStatus, Indeterminate<Error>> {
+        let mut status = SnpPlatformStatus::default();
+        self.issue(&mut status)?;
+        Ok(status)
+    }
+
+    /// Reset the platform to its factory state, deleting its persistent
+    /// keys and certificates.
+    pub fn platform_reset(&mut self) -> Result<(), Indeterminate<Error>> {
+        Ok(self.issue(&mut FactoryReset)?)
+    }
+
+    /// Generate a new platform endorsement key, invalidating any PEK and PDH
+    /// certificates previously issued.
+    pub fn pek_generate(&mut self) -> Result<(), Indeterminate<Error>> {
+        Ok(self.issue(&mut PekGen)?)
+    }
+
+    /// Generate a new platform Diffie-Hellman key.
+    pub fn pdh_generate(&mut self) -> Result<(), Indeterminate<Error>> {
+        Ok(self.issue(&mut PdhGen)?)
+    }
+
+    /// Generate a certificate signing request for the platform endorsement
+    /// key, querying the length of the request first.
+    pub fn pek_csr(&mut self) -> Result<Vec<u8>, Indeterminate<Error>> {
+        let mut query = PekCsr::default();
+        probe(self.issue(&mut query))?;
+
+        let mut buf = vec![0u8; query.length()];
+        let mut csr = PekCsr::new(&mut buf)?;
+        self.issue(&mut csr)?;
+
+        let len = csr.length();
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Export the platform Diffie-Hellman certificate and the chain of
+    /// certificates which signed it, querying their lengths first.
+    pub fn pdh_cert_export(&mut self) -> Result<(Vec<u8>, Vec<u8>), Indeterminate<Error>> {
+        let mut query = PdhCertExport::default();
+        probe(self.issue(&mut query))?;
+
+        let mut pdh_cert = vec![0u8; query.pdh_cert_length()];
+        let mut cert_chain = vec![0u8; query.cert_chain_length()];
+        let mut export = PdhCertExport::new(&mut pdh_cert, &mut cert_chain)?;
+        self.issue(&mut export)?;
+
+        let (pdh_cert_len, cert_chain_len) = (export.pdh_cert_length(), export.cert_chain_length());
+        pdh_cert.truncate(pdh_cert_len);
+        cert_chain.truncate(cert_chain_len);
+        Ok((pdh_cert, cert_chain))
+    }
+
+    /// Import the PEK certificate, signed by the owner's certificate
+    /// authority, along with the OCA certificate, taking ownership of the
+    /// platform.
+    pub fn pek_cert_import(
+        &mut self,
+        pek_cert: &[u8],
+        oca_cert: &[u8],
+    ) -> Result<(), Indeterminate<Error>> {
+        Ok(self.issue(&mut PekCertImport::new(pek_cert, oca_cert)?)?)
+    }
+
+    /// Retrieve the unique identifier of the chip, querying its length first.
+    ///
+    /// On machines with several sockets, this is the identifier of the chip
+    /// in the first socket.
+    pub fn get_id2(&mut self) -> Result<ChipId, Indeterminate<Error>> {
+        let mut query = GetId2::default();
+        probe(self.issue(&mut query))?;
+
+        let mut buf = vec![0u8; query.length()];
+        let mut id = GetId2::new(&mut buf)?;
+        self.issue(&mut id)?;
+
+        let len = id.length();
+        buf.truncate(len);
+        ChipId::first_socket(&buf).ok_or(Indeterminate::Unknown)
+    }
+
+    /// Commit the currently installed firmware and TCB version, preventing
+    /// rollback to an older version.
+    pub fn snp_commit(&mut self) -> Result<(), Indeterminate<Error>> {
+        Ok(self.issue(&mut SnpCommit::default())?)
+    }
+
+    /// Load a VLEK hashstick, wrapped by the AMD key distribution service,
+    /// so that attestation reports are signed with the VLEK instead of the
+    /// VCEK.
+    pub fn snp_vlek_load(
+        &mut self,
+        version: u8,
+        hashstick: &[u8; VLEK_HASHSTICK_SIZE],
+    ) -> Result<(), Indeterminate<Error>> {
+        Ok(self.issue(&mut SnpVlekLoad::new(version, hashstick))?)
+    }
+
+    /// Retrieve the unique identifier of the chip through the deprecated
+    /// `SEV_GET_ID` command, which is all that older kernels support.
+    pub fn get_id(&mut self) -> Result<ChipId, Indeterminate<Error>> {
+        let mut id = GetId::default();
+        self.issue(&mut id)?;
+        Ok(ChipId::from(id.socket1))
+    }
+
+    /// Retrieve the unique identifier of the chip, falling back to the
+    /// deprecated `SEV_GET_ID` command if the kernel does not support
+    /// `SEV_GET_ID2`.
+    pub fn get_identifier(&mut self) -> Result<ChipId, Indeterminate<Error>> {
+        match self.get_id2() {
+            Err(Indeterminate::Known(Error::IoError(e)))
+                if e.kind() == io::ErrorKind::InvalidInput =>
+            {
+                self.get_id()
+            }
+            result => result,
         }
     }
 }
 
+impl<T: PlatformId> SevDevice<T> for Firmware {
+    type Error = CommandError;
+
+    fn issue_ioctl(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        self.issue(subcmd)
+    }
+}
+
+impl AsFd for Firmware {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for Firmware {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl From<OwnedFd> for Firmware {
+    /// Use an already open `/dev/sev` file descriptor.
+    fn from(fd: OwnedFd) -> Self {
+        Firmware(fd)
+    }
+}
+
+impl From<Firmware> for OwnedFd {
+    fn from(firmware: Firmware) -> Self {
+        firmware.0
+    }
+}
+
+/// A handle to the SEV platform firmware which may be cloned and used from
+/// several threads at once, such as by an attestation service serving
+/// parallel requests.
+///
+/// The kernel serializes the commands issued through `/dev/sev`, so every
+/// method may be called concurrently with any other: commands which must be
+/// issued more than once, like the length probe of [`SharedFirmware::pek_csr`],
+/// hold the handle for the whole exchange. Commands which change the state of
+/// the platform, such as [`SharedFirmware::platform_reset`] or
+/// [`SharedFirmware::pek_generate`], invalidate the results of the commands
+/// issued before them, so sequences which must not be interleaved with those,
+/// such as generating a PEK CSR and importing its signed certificate, should
+/// be issued while holding [`SharedFirmware::lock`].
+#[derive(Clone, Debug)]
+pub struct SharedFirmware(Arc<Mutex<Firmware>>);
+
+impl SharedFirmware {
+    /// Share an open handle to the firmware.
+    pub fn new(firmware: Firmware) -> Self {
+        Self(Arc::new(Mutex::new(firmware)))
+    }
+
+    /// Open the SEV platform firmware device.
+    pub fn open() -> io::Result<SharedFirmware> {
+        Firmware::open().map(Self::new)
+    }
+
+    /// Take exclusive use of the firmware, so that a sequence of commands is
+    /// not interleaved with those of other threads.
+    pub fn lock(&self) -> MutexGuard<'_, Firmware> {
+        // A panic while issuing a command leaves no state behind to corrupt.
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// See [`Firmware::issue`].
+    pub fn issue<T: PlatformId>(&self, subcmd: &mut T) -> Result<(), CommandError> {
+        self.lock().issue(subcmd)
+    }
+
+    /// See [`Firmware::platform_status`].
+    pub fn platform_status(&self) -> Result<Status, Indeterminate<Error>> {
+        self.lock().platform_status()
+    }
+
+    /// See [`Firmware::snp_platform_status`].
+    pub fn snp_platform_status(&self) -> Result<SnpPlatformStatus, Indeterminate<Error>> {
+        self.lock().snp_platform_status()
+    }
+
+    /// See [`Firmware::platform_reset`].
+    pub fn platform_reset(&self) -> Result<(), Indeterminate<Error>> {
+        self.lock().platform_reset()
+    }
+
+    /// See [`Firmware::pek_generate`].
+    pub fn pek_generate(&self) -> Result<(), Indeterminate<Error>> {
+        self.lock().pek_generate()
+    }
+
+    /// See [`Firmware::pdh_generate`].
+    pub fn pdh_generate(&self) -> Result<(), Indeterminate<Error>> {
+        self.lock().pdh_generate()
+    }
+
+    /// See [`Firmware::pek_csr`].
+    pub fn pek_csr(&self) -> Result<Vec<u8>, Indeterminate<Error>> {
+        self.lock().pek_csr()
+    }
+
+    /// See [`Firmware::pdh_cert_export`].
+    pub fn pdh_cert_export(&self) -> Result<(Vec<u8>, Vec<u8>), Indeterminate<Error>> {
+        self.lock().pdh_cert_export()
+    }
+
+    /// See [`Firmware::pek_cert_import`].
+    pub fn pek_cert_import(
+        &self,
+        pek_cert: &[u8],
+        oca_cert: &[u8],
+    ) -> Result<(), Indeterminate<Error>> {
+        self.lock().pek_cert_import(pek_cert, oca_cert)
+    }
+
+    /// See [`Firmware::get_id2`].
+    pub fn get_id2(&self) -> Result<ChipId, Indeterminate<Error>> {
+        self.lock().get_id2()
+    }
+
+    /// See [`Firmware::snp_commit`].
+    pub fn snp_commit(&self) -> Result<(), Indeterminate<Error>> {
+        self.lock().snp_commit()
+    }
+
+    /// See [`Firmware::snp_vlek_load`].
+    pub fn snp_vlek_load(
+        &self,
+        version: u8,
+        hashstick: &[u8; VLEK_HASHSTICK_SIZE],
+    ) -> Result<(), Indeterminate<Error>> {
+        self.lock().snp_vlek_load(version, hashstick)
+    }
+
+    /// See [`Firmware::get_id`].
+    pub fn get_id(&self) -> Result<ChipId, Indeterminate<Error>> {
+        self.lock().get_id()
+    }
+
+    /// See [`Firmware::get_identifier`].
+    pub fn get_identifier(&self) -> Result<ChipId, Indeterminate<Error>> {
+        self.lock().get_identifier()
+    }
+}
+
+impl From<Firmware> for SharedFirmware {
+    fn from(firmware: Firmware) -> Self {
+        Self::new(firmware)
+    }
+}
+
+impl<T: PlatformId> SevDevice<T> for SharedFirmware {
+    type Error = CommandError;
+
+    fn issue_ioctl(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        self.issue(subcmd)
+    }
+}
+
 /// Information about the SEV-SNP platform version.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
@@ -79,8 +460,1039 @@ pub struct Version {
     pub minor: u8,
 }
 
+assert_layout!(Version, 2, 1);
+
 impl std::fmt::Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}.{}", self.major, self.minor)
     }
 }
+
+/// The state of the SEV platform.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PlatformState {
+    /// The platform is not initialized.
+    Uninit,
+
+    /// The platform is initialized, but no guests are running.
+    Init,
+
+    /// The platform is initialized and at least one guest is running.
+    Working,
+}
+
+impl TryFrom<u8> for PlatformState {
+    type Error = u8;
+
+    fn try_from(state: u8) -> Result<Self, u8> {
+        Ok(match state {
+            0 => PlatformState::Uninit,
+            1 => PlatformState::Init,
+            2 => PlatformState::Working,
+            _ => return Err(state),
+        })
+    }
+}
+
+impl PlatformState {
+    /// Whether the given platform command may be issued through `/dev/sev`
+    /// while the platform is in this state.
+    ///
+    /// The kernel initializes the platform on demand, so commands which the
+    /// firmware only accepts in the `INIT` state are also legal in the
+    /// `UNINIT` state. The SEV-SNP commands are governed by the SNP platform
+    /// state instead and are always reported as legal.
+    pub fn permits(&self, code: CommandCode) -> bool {
+        let idle = *self != PlatformState::Working;
+
+        match code {
+            CommandCode::FactoryReset => idle,
+            CommandCode::PlatformStatus => true,
+            CommandCode::PekGen => idle,
+            CommandCode::PekCsr => idle,
+            CommandCode::PdhGen => true,
+            CommandCode::PdhCertExport => true,
+            CommandCode::PekCertImport => idle,
+            CommandCode::GetId => true,
+            CommandCode::GetId2 => true,
+            CommandCode::SnpPlatformStatus => true,
+            CommandCode::SnpCommit => true,
+            CommandCode::SnpVlekLoad => true,
+        }
+    }
+}
+
+bitflags! {
+    /// The configuration of the SEV platform.
+    #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct PlatformStatusFlags: u32 {
+        /// The platform is owned by an external entity rather than itself.
+        const OWNED = 1 << 0;
+
+        /// SEV-ES is initialized on the platform.
+        const ENCRYPTED_STATE = 1 << 8;
+    }
+}
+
+/// The status of the SEV platform.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Status {
+    /// The version of the firmware API.
+    pub version: Version,
+
+    /// The build number of the firmware.
+    pub build: u8,
+
+    /// The state of the platform.
+    pub state: PlatformState,
+
+    /// The number of valid guests managed by the firmware.
+    pub guests: u32,
+
+    /// The configuration of the platform.
+    pub flags: PlatformStatusFlags,
+}
+
+/// Query the status of the SEV platform.
+///
+/// Corresponds to the kernel struct `sev_user_data_status`.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PlatformStatus {
+    api_major: u8,
+    api_minor: u8,
+    state: u8,
+    flags: u32,
+    build: u8,
+    guest_count: u32,
+}
+
+assert_layout!(PlatformStatus, 12, 1);
+
+/// Query the status of the SEV-SNP platform.
+///
+/// Corresponds to the kernel struct `sev_user_data_snp_status`.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SnpPlatformStatus {
+    api_major: u8,
+    api_minor: u8,
+    state: u8,
+    rmp_initialized: u8,
+    build_id: u32,
+    config: u32,
+    guest_count: u32,
+    current_tcb_version: u64,
+    reported_tcb_version: u64,
+}
+
+assert_layout!(SnpPlatformStatus, 32, 1);
+
+impl SnpPlatformStatus {
+    /// The version of the firmware API.
+    pub fn version(&self) -> Version {
+        Version {
+            major: self.api_major,
+            minor: self.api_minor,
+        }
+    }
+
+    /// The state of the platform.
+    pub fn state(&self) -> Indeterminate<PlatformState> {
+        PlatformState::try_from(self.state).map_or(Indeterminate::Unknown, Indeterminate::Known)
+    }
+
+    /// Whether the reverse map table has been initialized.
+    pub fn rmp_initialized(&self) -> bool {
+        self.rmp_initialized & 1 != 0
+    }
+
+    /// The build number of the firmware.
+    pub fn build(&self) -> u32 {
+        self.build_id
+    }
+
+    /// Whether the chip ID is masked in attestation reports.
+    pub fn mask_chip_id(&self) -> bool {
+        self.config & (1 << 0) != 0
+    }
+
+    /// Whether the chip key is masked, so that reports are not signed with it.
+    pub fn mask_chip_key(&self) -> bool {
+        self.config & (1 << 1) != 0
+    }
+
+    /// Whether a VLEK has been loaded and is used to sign reports.
+    pub fn vlek_enabled(&self) -> bool {
+        self.config & (1 << 2) != 0
+    }
+
+    /// The number of valid guests managed by the firmware.
+    pub fn guests(&self) -> u32 {
+        self.guest_count
+    }
+
+    /// The TCB version of the platform.
+    pub fn current_tcb(&self) -> TcbVersion {
+        self.current_tcb_version.into()
+    }
+
+    /// The TCB version reported in attestation reports.
+    pub fn reported_tcb(&self) -> TcbVersion {
+        self.reported_tcb_version.into()
+    }
+}
+
+/// Reset the platform to its factory state.
+///
+/// Corresponds to the `SEV_FACTORY_RESET` command, which takes no parameters.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FactoryReset;
+
+/// Generate a new platform endorsement key.
+///
+/// Corresponds to the `SEV_PEK_GEN` command, which takes no parameters.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PekGen;
+
+/// Generate a new platform Diffie-Hellman key.
+///
+/// Corresponds to the `SEV_PDH_GEN` command, which takes no parameters.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PdhGen;
+
+/// Generate a certificate signing request for the platform endorsement key.
+///
+/// Corresponds to the kernel struct `sev_user_data_pek_csr`. Issuing the
+/// command with an empty buffer queries its required length instead;
+/// [`Firmware::pek_csr`] takes care of sizing the buffer.
+#[repr(C, packed)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PekCsr<'a> {
+    address: UserPtr<[u8]>,
+    length: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(PekCsr<'static>, 12, 1);
+
+impl<'a> PekCsr<'a> {
+    /// Create a new `PekCsr` referencing the buffer which receives the
+    /// certificate signing request.
+    pub fn new(csr: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            address: UserPtr::from_mut(csr),
+            length: try_len_u32(csr)?,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the certificate signing request, as reported by the
+    /// firmware.
+    pub fn length(&self) -> usize {
+        self.length as _
+    }
+}
+
+/// Export the platform Diffie-Hellman certificate and its certificate chain.
+///
+/// Corresponds to the kernel struct `sev_user_data_pdh_cert_export`. The
+/// chain holds the PEK, OCA and CEK certificates. Issuing the command with
+/// empty buffers queries their required lengths instead;
+/// [`Firmware::pdh_cert_export`] takes care of sizing the buffers.
+#[repr(C, packed)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PdhCertExport<'a> {
+    pdh_cert_address: UserPtr<[u8]>,
+    pdh_cert_len: u32,
+    cert_chain_address: UserPtr<[u8]>,
+    cert_chain_len: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(PdhCertExport<'static>, 24, 1);
+
+impl<'a> PdhCertExport<'a> {
+    /// Create a new `PdhCertExport` referencing the buffers which receive the
+    /// PDH certificate and the certificate chain.
+    pub fn new(pdh_cert: &'a mut [u8], cert_chain: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            pdh_cert_address: UserPtr::from_mut(pdh_cert),
+            pdh_cert_len: try_len_u32(pdh_cert)?,
+            cert_chain_address: UserPtr::from_mut(cert_chain),
+            cert_chain_len: try_len_u32(cert_chain)?,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the PDH certificate, as reported by the firmware.
+    pub fn pdh_cert_length(&self) -> usize {
+        self.pdh_cert_len as _
+    }
+
+    /// The length of the certificate chain, as reported by the firmware.
+    pub fn cert_chain_length(&self) -> usize {
+        self.cert_chain_len as _
+    }
+}
+
+/// Import a signed PEK certificate and the owner's certificate authority
+/// certificate.
+///
+/// Corresponds to the kernel struct `sev_user_data_pek_cert_import`.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PekCertImport<'a> {
+    pek_cert_address: UserPtr<[u8]>,
+    pek_cert_len: u32,
+    oca_cert_address: UserPtr<[u8]>,
+    oca_cert_len: u32,
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(PekCertImport<'static>, 24, 1);
+
+impl<'a> PekCertImport<'a> {
+    /// Create a new `PekCertImport` referencing the PEK certificate signed
+    /// with the OCA, and the OCA certificate itself.
+    pub fn new(pek_cert: &'a [u8], oca_cert: &'a [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            pek_cert_address: UserPtr::from_ref(pek_cert),
+            pek_cert_len: try_len_u32(pek_cert)?,
+            oca_cert_address: UserPtr::from_ref(oca_cert),
+            oca_cert_len: try_len_u32(oca_cert)?,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// The unique identifier of a chip, as used to fetch its VCEK certificate
+/// from the AMD key distribution service.
+///
+/// The identifier is displayed, parsed and serialized as lowercase
+/// hexadecimal, which is the `hwid` the key distribution service expects.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct ChipId(pub ByteArray<64>);
+
+// SAFETY: the identifier is a byte array.
+unsafe impl SwapBytes for ChipId {
+    fn swap_bytes(self) -> Self {
+        self
+    }
+}
+
+impl ChipId {
+    /// The raw bytes of the identifier.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// Parse an identifier from exactly 128 hexadecimal digits.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        ByteArray::from_hex(hex).map(Self)
+    }
+
+    /// Take the identifier of the first socket from the raw output of
+    /// `SEV_GET_ID2`, which holds one identifier per socket.
+    pub fn first_socket(id: &[u8]) -> Option<Self> {
+        id.get(..64).and_then(|first| Self::try_from(first).ok())
+    }
+}
+
+impl From<[u8; 64]> for ChipId {
+    fn from(id: [u8; 64]) -> Self {
+        Self(ByteArray(id))
+    }
+}
+
+impl From<ByteArray<64>> for ChipId {
+    fn from(id: ByteArray<64>) -> Self {
+        Self(id)
+    }
+}
+
+impl From<ChipId> for ByteArray<64> {
+    fn from(id: ChipId) -> Self {
+        id.0
+    }
+}
+
+impl PartialEq<[u8; 64]> for ChipId {
+    fn eq(&self, other: &[u8; 64]) -> bool {
+        self.0 == *other
+    }
+}
+
+impl AsRef<[u8]> for ChipId {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl std::str::FromStr for ChipId {
+    type Err = io::Error;
+
+    fn from_str(hex: &str) -> io::Result<Self> {
+        Self::from_hex(hex)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid chip ID"))
+    }
+}
+
+impl std::fmt::Display for ChipId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::fmt::Debug for ChipId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ChipId({})", self)
+    }
+}
+
+impl TryFrom<&[u8]> for ChipId {
+    type Error = std::array::TryFromSliceError;
+
+    /// Convert a slice of exactly 64 bytes into an identifier; see
+    /// [`ChipId::first_socket`] for the output of `SEV_GET_ID2`.
+    fn try_from(id: &[u8]) -> Result<Self, Self::Error> {
+        <[u8; 64]>::try_from(id).map(Self::from)
+    }
+}
+
+/// Retrieve the unique identifiers of the chips in up to two sockets.
+///
+/// Corresponds to the kernel struct `sev_user_data_get_id`. This command is
+/// deprecated in favor of [`GetId2`].
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetId {
+    socket1: [u8; 64],
+    socket2: [u8; 64],
+}
+
+assert_layout!(GetId, 128, 1);
+
+impl Default for GetId {
+    fn default() -> Self {
+        Self {
+            socket1: [0; 64],
+            socket2: [0; 64],
+        }
+    }
+}
+
+impl GetId {
+    /// The identifier of the chip in the first socket.
+    pub fn socket1(&self) -> &[u8; 64] {
+        &self.socket1
+    }
+
+    /// The identifier of the chip in the second socket, if any.
+    pub fn socket2(&self) -> &[u8; 64] {
+        &self.socket2
+    }
+}
+
+/// Retrieve the unique identifier of the chip.
+///
+/// Corresponds to the kernel struct `sev_user_data_get_id2`. The identifier is
+/// needed to fetch the VCEK certificate of the chip from the AMD key
+/// distribution service. Issuing the command with an empty buffer queries its
+/// required length instead; [`Firmware::get_id2`] takes care of sizing the
+/// buffer.
+#[repr(C, packed)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GetId2<'a> {
+    address: UserPtr<[u8]>,
+    length: u32,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+assert_layout!(GetId2<'static>, 12, 1);
+
+impl<'a> GetId2<'a> {
+    /// Create a new `GetId2` referencing the buffer which receives the
+    /// identifier.
+    pub fn new(id: &'a mut [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            address: UserPtr::from_mut(id),
+            length: try_len_u32(id)?,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The length of the identifier, as reported by the firmware.
+    pub fn length(&self) -> usize {
+        self.length as _
+    }
+}
+
+/// Commit the currently installed firmware and TCB version.
+///
+/// Corresponds to the firmware struct `sev_data_snp_commit`. The kernel builds
+/// this struct itself when handling `SNP_COMMIT`, so its contents only matter
+/// to callers forwarding the command elsewhere.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SnpCommit {
+    len: u32,
+}
+
+assert_layout!(SnpCommit, 4, 1);
+
+impl Default for SnpCommit {
+    fn default() -> Self {
+        Self {
+            len: size_of::<Self>() as _,
+        }
+    }
+}
+
+/// Download a new firmware image to the platform.
+///
+/// Corresponds to the firmware struct `sev_data_download_firmware`. Linux does
+/// not expose this command through `/dev/sev`: the kernel downloads the image
+/// found under `/lib/firmware/amd` itself while initializing the platform, so
+/// an update is installed by replacing that image and reloading the `ccp`
+/// module. For this reason `DownloadFirmware` has no [`Id`] and cannot be
+/// passed to [`Firmware::issue`].
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DownloadFirmware<'a> {
+    address: UserPtr<[u8]>,
+    len: u32,
+    phantom: PhantomData<&'a [u8]>,
+}
+
+assert_layout!(DownloadFirmware<'static>, 12, 1);
+
+impl<'a> DownloadFirmware<'a> {
+    /// Create a new `DownloadFirmware` referencing the firmware image.
+    pub fn new(image: &'a [u8]) -> Result<Self, InputError> {
+        Ok(Self {
+            address: UserPtr::from_ref(image),
+            len: try_len_u32(image)?,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// The size of a wrapped VLEK hashstick.
+pub const VLEK_HASHSTICK_SIZE: usize = 432;
+
+/// Load a wrapped versioned loaded endorsement key.
+///
+/// Corresponds to the kernel struct `sev_user_data_snp_vlek_load`.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SnpVlekLoad<'a> {
+    len: u32,
+    vlek_wrapped_version: u8,
+    rsvd: [u8; 3],
+    vlek_wrapped_address: UserPtr<[u8; VLEK_HASHSTICK_SIZE]>,
+    phantom: PhantomData<&'a [u8; VLEK_HASHSTICK_SIZE]>,
+}
+
+assert_layout!(SnpVlekLoad<'static>, 16, 1);
+
+impl<'a> SnpVlekLoad<'a> {
+    /// Create a new `SnpVlekLoad` referencing a hashstick wrapped with the
+    /// given version of the wrapping scheme.
+    pub fn new(version: u8, hashstick: &'a [u8; VLEK_HASHSTICK_SIZE]) -> Self {
+        Self {
+            len: size_of::<Self>() as _,
+            vlek_wrapped_version: version,
+            rsvd: [0; 3],
+            vlek_wrapped_address: UserPtr::from_ref(hashstick),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl_const_id! {
+    pub Id: PlatformId => u32 as CommandCode;
+    FactoryReset = 0,
+    PlatformStatus = 1,
+    PekGen = 2,
+    PekCsr<'_> = 3,
+    PdhGen = 4,
+    PdhCertExport<'_> = 5,
+    PekCertImport<'_> = 6,
+    GetId = 7,
+    GetId2<'_> = 8,
+    SnpPlatformStatus = 256,
+    SnpCommit = 257,
+    SnpVlekLoad<'_> = 259,
+}
+
+impl_pod! {
+    FactoryReset,
+    PlatformStatus,
+    PekGen,
+    PdhGen,
+    GetId,
+    SnpPlatformStatus,
+    SnpCommit,
+}
+
+/// The security version numbers of the components making up the trusted
+/// computing base of an SEV-SNP platform.
+///
+/// A TCB version is only newer than another if none of its components are
+/// older, so two TCB versions may not be comparable at all.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TcbVersion {
+    /// The SVN of the PSP bootloader.
+    pub bootloader: u8,
+
+    /// The SVN of the PSP operating system.
+    pub tee: u8,
+
+    /// The SVN of the SNP firmware.
+    pub snp: u8,
+
+    /// The lowest current patch level of all the cores.
+    pub microcode: u8,
+}
+
+impl TcbVersion {
+    /// Create a new `TcbVersion`.
+    pub const fn new(bootloader: u8, tee: u8, snp: u8, microcode: u8) -> Self {
+        Self {
+            bootloader,
+            tee,
+            snp,
+            microcode,
+        }
+    }
+
+    fn components(&self) -> [u8; 4] {
+        [self.bootloader, self.tee, self.snp, self.microcode]
+    }
+}
+
+impl From<u64> for TcbVersion {
+    fn from(raw: u64) -> Self {
+        let bytes = raw.to_le_bytes();
+        Self::new(bytes[0], bytes[1], bytes[6], bytes[7])
+    }
+}
+
+impl From<TcbVersion> for u64 {
+    fn from(tcb: TcbVersion) -> Self {
+        u64::from_le_bytes([tcb.bootloader, tcb.tee, 0, 0, 0, 0, tcb.snp, tcb.microcode])
+    }
+}
+
+impl std::cmp::PartialOrd for TcbVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        use std::cmp::Ordering::*;
+
+        let mut ordering = Equal;
+        for (lhs, rhs) in self.components().iter().zip(other.components().iter()) {
+            match (ordering, lhs.cmp(rhs)) {
+                (_, Equal) => (),
+                (Equal, o) => ordering = o,
+                (a, b) if a != b => return None,
+                _ => (),
+            }
+        }
+
+        Some(ordering)
+    }
+}
+
+impl std::fmt::Display for TcbVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.bootloader, self.tee, self.snp, self.microcode
+        )
+    }
+}
+
+/// The policy of an SEV-SNP guest, which the firmware enforces for the
+/// lifetime of the guest.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u64", into = "u64"))]
+pub struct GuestPolicy(u64);
+
+impl GuestPolicy {
+    const ABI_MINOR_SHIFT: u64 = 0;
+    const ABI_MAJOR_SHIFT: u64 = 8;
+    const SMT: u64 = 1 << 16;
+    const RESERVED_ONE: u64 = 1 << 17;
+    const MIGRATE_MA: u64 = 1 << 18;
+    const DEBUG: u64 = 1 << 19;
+    const SINGLE_SOCKET: u64 = 1 << 20;
+    const CXL_ALLOW: u64 = 1 << 21;
+    const MEM_AES_256_XTS: u64 = 1 << 22;
+    const RAPL_DIS: u64 = 1 << 23;
+    const CIPHERTEXT_HIDING: u64 = 1 << 24;
+    const PAGE_SWAP_DISABLE: u64 = 1 << 25;
+    const RESERVED: u64 = !((1 << 26) - 1);
+
+    /// Create a new `GuestPolicy` requiring at least the given firmware ABI
+    /// version, with every other feature disallowed.
+    pub const fn new(abi_major: u8, abi_minor: u8) -> Self {
+        Self(
+            Self::RESERVED_ONE
+                | (abi_major as u64) << Self::ABI_MAJOR_SHIFT
+                | (abi_minor as u64) << Self::ABI_MINOR_SHIFT,
+        )
+    }
+
+    /// Wrap a policy reported by the firmware without validating it.
+    pub(crate) const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    fn get(&self, bit: u64) -> bool {
+        self.0 & bit != 0
+    }
+
+    fn set(&mut self, bit: u64, value: bool) {
+        if value {
+            self.0 |= bit;
+        } else {
+            self.0 &= !bit;
+        }
+    }
+
+    /// The minimum major version of the firmware ABI.
+    pub fn abi_major(&self) -> u8 {
+        (self.0 >> Self::ABI_MAJOR_SHIFT) as u8
+    }
+
+    /// Set the minimum major version of the firmware ABI.
+    pub fn set_abi_major(&mut self, major: u8) {
+        self.0 &= !(0xff << Self::ABI_MAJOR_SHIFT);
+        self.0 |= (major as u64) << Self::ABI_MAJOR_SHIFT;
+    }
+
+    /// The minimum minor version of the firmware ABI.
+    pub fn abi_minor(&self) -> u8 {
+        (self.0 >> Self::ABI_MINOR_SHIFT) as u8
+    }
+
+    /// Set the minimum minor version of the firmware ABI.
+    pub fn set_abi_minor(&mut self, minor: u8) {
+        self.0 &= !(0xff << Self::ABI_MINOR_SHIFT);
+        self.0 |= (minor as u64) << Self::ABI_MINOR_SHIFT;
+    }
+
+    /// Whether the guest may run while simultaneous multithreading is enabled.
+    pub fn smt(&self) -> bool {
+        self.get(Self::SMT)
+    }
+
+    /// Allow or disallow running while simultaneous multithreading is enabled.
+    pub fn set_smt(&mut self, allowed: bool) {
+        self.set(Self::SMT, allowed)
+    }
+
+    /// Whether the guest may be associated with a migration agent.
+    pub fn migrate_ma(&self) -> bool {
+        self.get(Self::MIGRATE_MA)
+    }
+
+    /// Allow or disallow association with a migration agent.
+    pub fn set_migrate_ma(&mut self, allowed: bool) {
+        self.set(Self::MIGRATE_MA, allowed)
+    }
+
+    /// Whether the guest may be debugged.
+    pub fn debug(&self) -> bool {
+        self.get(Self::DEBUG)
+    }
+
+    /// Allow or disallow debugging the guest.
+    pub fn set_debug(&mut self, allowed: bool) {
+        self.set(Self::DEBUG, allowed)
+    }
+
+    /// Whether the guest may only be activated on one socket.
+    pub fn single_socket(&self) -> bool {
+        self.get(Self::SINGLE_SOCKET)
+    }
+
+    /// Require or stop requiring that the guest is activated on one socket.
+    pub fn set_single_socket(&mut self, required: bool) {
+        self.set(Self::SINGLE_SOCKET, required)
+    }
+
+    /// Whether CXL memory may be populated with guest private memory.
+    pub fn cxl_allow(&self) -> bool {
+        self.get(Self::CXL_ALLOW)
+    }
+
+    /// Allow or disallow populating CXL memory with guest private memory.
+    pub fn set_cxl_allow(&mut self, allowed: bool) {
+        self.set(Self::CXL_ALLOW, allowed)
+    }
+
+    /// Whether guest memory must be encrypted with AES-256-XTS.
+    pub fn mem_aes_256_xts(&self) -> bool {
+        self.get(Self::MEM_AES_256_XTS)
+    }
+
+    /// Require or stop requiring AES-256-XTS for guest memory encryption.
+    pub fn set_mem_aes_256_xts(&mut self, required: bool) {
+        self.set(Self::MEM_AES_256_XTS, required)
+    }
+
+    /// Whether the guest requires Running Average Power Limit (RAPL) to be
+    /// disabled.
+    pub fn rapl_dis(&self) -> bool {
+        self.get(Self::RAPL_DIS)
+    }
+
+    /// Require or stop requiring that RAPL is disabled.
+    pub fn set_rapl_dis(&mut self, required: bool) {
+        self.set(Self::RAPL_DIS, required)
+    }
+
+    /// Whether the guest requires ciphertext hiding to be enabled.
+    pub fn ciphertext_hiding(&self) -> bool {
+        self.get(Self::CIPHERTEXT_HIDING)
+    }
+
+    /// Require or stop requiring that ciphertext hiding is enabled.
+    pub fn set_ciphertext_hiding(&mut self, required: bool) {
+        self.set(Self::CIPHERTEXT_HIDING, required)
+    }
+
+    /// Whether the guest disallows the `SNP_PAGE_MOVE`, `SNP_SWAP_OUT` and
+    /// `SNP_SWAP_IN` commands.
+    pub fn page_swap_disable(&self) -> bool {
+        self.get(Self::PAGE_SWAP_DISABLE)
+    }
+
+    /// Disallow or allow swapping the pages of the guest.
+    pub fn set_page_swap_disable(&mut self, disabled: bool) {
+        self.set(Self::PAGE_SWAP_DISABLE, disabled)
+    }
+}
+
+impl Default for GuestPolicy {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl TryFrom<u64> for GuestPolicy {
+    type Error = u64;
+
+    /// Validate a raw policy, returning it unchanged as the error if any
+    /// reserved bit is set or the bit which must be one is clear.
+    fn try_from(raw: u64) -> Result<Self, u64> {
+        if raw & Self::RESERVED != 0 || raw & Self::RESERVED_ONE == 0 {
+            return Err(raw);
+        }
+
+        Ok(Self(raw))
+    }
+}
+
+impl From<GuestPolicy> for u64 {
+    fn from(policy: GuestPolicy) -> Self {
+        policy.0
+    }
+}
+
+bitflags! {
+    /// The policy of a legacy SEV or SEV-ES guest.
+    ///
+    /// Besides the flags, the policy carries the minimum version of the
+    /// firmware API which the guest may be launched on; see
+    /// [`SevPolicy::with_minimum_api`].
+    #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct SevPolicy: u32 {
+        /// Debugging of the guest is disallowed.
+        const NODBG = 1 << 0;
+
+        /// Sharing keys with other guests is disallowed.
+        const NOKS = 1 << 1;
+
+        /// SEV-ES is required.
+        const ES = 1 << 2;
+
+        /// Sending the guest to another platform is disallowed.
+        const NOSEND = 1 << 3;
+
+        /// The guest may only be sent to another platform in the same domain.
+        const DOMAIN = 1 << 4;
+
+        /// The guest may only be sent to another platform supporting SEV.
+        const SEV = 1 << 5;
+
+        /// The minimum major version of the firmware API.
+        const API_MAJOR = 0xff << 16;
+
+        /// The minimum minor version of the firmware API.
+        const API_MINOR = 0xff << 24;
+    }
+}
+
+impl SevPolicy {
+    /// Require at least the given version of the firmware API, replacing any
+    /// version previously required.
+    pub fn with_minimum_api(self, version: Version) -> Self {
+        let api = (version.major as u32) << 16 | (version.minor as u32) << 24;
+        (self - Self::API_MAJOR - Self::API_MINOR) | Self::from_bits_truncate(api)
+    }
+
+    /// The minimum version of the firmware API required by the policy.
+    pub fn minimum_api(&self) -> Version {
+        Version {
+            major: (self.bits() >> 16) as u8,
+            minor: (self.bits() >> 24) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn tcb_version_order() {
+        let base = TcbVersion::new(3, 0, 8, 115);
+
+        assert_eq!(base.partial_cmp(&base), Some(Ordering::Equal));
+        assert!(base < TcbVersion::new(3, 0, 8, 209));
+        assert!(base < TcbVersion::new(4, 0, 9, 115));
+        assert!(base > TcbVersion::new(2, 0, 8, 115));
+
+        let newer_fw = TcbVersion::new(3, 0, 9, 93);
+        assert_eq!(base.partial_cmp(&newer_fw), None);
+        assert_eq!(newer_fw.partial_cmp(&base), None);
+        assert!(!newer_fw.ge(&base) && !newer_fw.le(&base));
+    }
+
+    #[test]
+    fn tcb_version_display() {
+        assert_eq!(TcbVersion::new(3, 0, 0, 115).to_string(), "03:00:00:115");
+        assert_eq!(TcbVersion::new(0, 0, 0, 0).to_string(), "00:00:00:00");
+    }
+
+    #[test]
+    fn tcb_version_u64() {
+        let raw = 0x7308_0000_0000_0003;
+        let tcb = TcbVersion::from(raw);
+
+        assert_eq!(tcb, TcbVersion::new(3, 0, 8, 0x73));
+        assert_eq!(u64::from(tcb), raw);
+
+        // The reserved bytes do not survive the round trip.
+        assert_eq!(u64::from(TcbVersion::from(raw | 0x00ff_ffff_0000)), raw);
+    }
+
+    #[test]
+    fn guest_policy_reserved_bits() {
+        let policy = GuestPolicy::new(1, 51);
+        assert_eq!(u64::from(policy), 0x2_0133);
+        assert_eq!(GuestPolicy::try_from(0x2_0133), Ok(policy));
+
+        // Bit 17 must be set.
+        assert_eq!(GuestPolicy::try_from(0x0_0133), Err(0x0_0133));
+
+        // Every bit above the page swap bit is reserved.
+        for bit in 26..64 {
+            let raw = 0x2_0000 | 1 << bit;
+            assert_eq!(GuestPolicy::try_from(raw), Err(raw));
+        }
+    }
+
+    #[test]
+    fn guest_policy_flags() {
+        let mut policy = GuestPolicy::default();
+        policy.set_smt(true);
+        policy.set_debug(true);
+        policy.set_page_swap_disable(true);
+        assert_eq!(u64::from(policy), 1 << 25 | 1 << 19 | 1 << 17 | 1 << 16);
+
+        let policy = GuestPolicy::try_from(u64::from(policy)).unwrap();
+        assert!(policy.smt() && policy.debug() && policy.page_swap_disable());
+        assert!(!policy.migrate_ma() && !policy.cxl_allow());
+    }
+
+    #[test]
+    fn platform_state_permits() {
+        use PlatformState::*;
+
+        let idle_only = [
+            CommandCode::FactoryReset,
+            CommandCode::PekGen,
+            CommandCode::PekCsr,
+            CommandCode::PekCertImport,
+        ];
+
+        for code in (0..=0x1ff).filter_map(|id| CommandCode::try_from(id).ok()) {
+            assert!(Uninit.permits(code), "{}", code);
+            assert!(Init.permits(code), "{}", code);
+            assert_eq!(
+                Working.permits(code),
+                !idle_only.contains(&code),
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn platform_state_raw() {
+        assert_eq!(PlatformState::try_from(0), Ok(PlatformState::Uninit));
+        assert_eq!(PlatformState::try_from(1), Ok(PlatformState::Init));
+        assert_eq!(PlatformState::try_from(2), Ok(PlatformState::Working));
+        assert_eq!(PlatformState::try_from(3), Err(3));
+    }
+
+    fn chip_id() -> ChipId {
+        let mut id = [0u8; 64];
+        id[0] = 0xab;
+        id[1] = 0x01;
+        id[63] = 0xf0;
+        ChipId::from(id)
+    }
+
+    #[test]
+    fn chip_id_display() {
+        let hex = format!("ab01{}f0", "00".repeat(61));
+        assert_eq!(chip_id().to_string(), hex);
+        assert_eq!(hex.parse::<ChipId>().unwrap(), chip_id());
+        assert_eq!(hex.to_uppercase().parse::<ChipId>().unwrap(), chip_id());
+        assert!(hex[2..].parse::<ChipId>().is_err());
+    }
+
+    #[test]
+    fn chip_id_first_socket() {
+        let mut id2 = chip_id().as_bytes().to_vec();
+        id2.extend_from_slice(&[0xff; 64]);
+        assert_eq!(ChipId::first_socket(&id2), Some(chip_id()));
+        assert_eq!(ChipId::first_socket(&id2[..63]), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn chip_id_serde() {
+        let json = serde_json::to_string(&chip_id()).unwrap();
+        assert_eq!(json, format!("\"{}\"", chip_id()));
+        assert_eq!(serde_json::from_str::<ChipId>(&json).unwrap(), chip_id());
+
+        let short = format!("\"{}\"", &chip_id().to_string()[2..]);
+        assert!(serde_json::from_str::<ChipId>(&short).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// Snapshots of command payloads, for recording and replaying launch sequences.
+use crate::sev::Id;
+use crate::util::{TypeLoad, TypeSave};
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::mem::{size_of, MaybeUninit};
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+
+/// The bytes identifying the start of a snapshot.
+pub const MAGIC: [u8; 4] = *b"SEVC";
+
+/// The version of the snapshot format written by this crate.
+pub const VERSION: u32 = 1;
+
+/// The header preceding the payload of a command in a snapshot.
+///
+/// Every field is stored in little endian, while the payload is stored exactly
+/// as it is laid out in memory, so a snapshot can only be replayed on the same
+/// architecture it was recorded on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    version: u32,
+    id: u32,
+    size: u32,
+}
+
+impl Header {
+    /// The header describing a payload of type `T`.
+    pub fn of<T: Id>() -> Self {
+        Self {
+            version: VERSION,
+            id: T::ID,
+            size: size_of::<T>() as _,
+        }
+    }
+
+    /// The version of the snapshot format.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The ID of the command whose payload follows the header.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The size of the payload following the header.
+    pub fn size(&self) -> usize {
+        self.size as _
+    }
+
+    /// Read a header, checking its magic and version.
+    pub fn read(reader: &mut impl Read) -> Result<Self> {
+        let magic: [u8; 4] = reader.load_le()?;
+        if magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a command snapshot"));
+        }
+
+        let version = reader.load_le()?;
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported snapshot version {}", version),
+            ));
+        }
+
+        Ok(Self {
+            version,
+            id: reader.load_le()?,
+            size: reader.load_le()?,
+        })
+    }
+
+    /// Write the header.
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.save_le(&MAGIC)?;
+        writer.save_le(&self.version)?;
+        writer.save_le(&self.id)?;
+        writer.save_le(&self.size)
+    }
+
+    /// Read the payload described by this header as a `T`, failing if the
+    /// header describes another command.
+    ///
+    /// # Safety
+    ///
+    /// The payload is read directly into the memory of a `T`, so the caller
+    /// must ensure it holds a valid `T`. Addresses stored in the payload refer
+    /// to the memory of the process which recorded it, so a loaded command
+    /// must not be issued unless its addresses have been replaced.
+    pub unsafe fn load_payload<T: Id>(&self, reader: &mut impl Read) -> Result<T> {
+        if *self != Self::of::<T>() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("snapshot holds command {} of {} bytes", self.id, self.size),
+            ));
+        }
+
+        let mut t = MaybeUninit::<T>::uninit();
+        reader.read_exact(from_raw_parts_mut(t.as_mut_ptr() as _, size_of::<T>()))?;
+        Ok(t.assume_init())
+    }
+}
+
+/// Write a snapshot of the command payload `value`.
+///
+/// # Safety
+///
+/// The payload is written directly from the memory of `value`, so the caller
+/// must ensure that `T` has no padding or other uninitialized bytes.
+pub unsafe fn save<T: Id>(writer: &mut impl Write, value: &T) -> Result<()> {
+    Header::of::<T>().write(writer)?;
+
+    let p = value as *const T as *const u8;
+    writer.write_all(from_raw_parts(p, size_of::<T>()))
+}
+
+/// Read a snapshot of a command payload of type `T`.
+///
+/// # Safety
+///
+/// See [`Header::load_payload`].
+pub unsafe fn load<T: Id>(reader: &mut impl Read) -> Result<T> {
+    Header::read(reader)?.load_payload(reader)
+}
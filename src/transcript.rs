@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// Transcripts of the commands issued to a device, for replaying them later.
+use crate::device::SevDevice;
+use crate::error::CommandError;
+use crate::sev::Id;
+
+use bytemuck::NoUninit;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A command issued to a device along with its outcome.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Entry {
+    /// The ID of the command.
+    pub id: u32,
+
+    /// The payload of the command before it was issued.
+    pub input: Vec<u8>,
+
+    /// The payload of the command after it was issued.
+    pub output: Vec<u8>,
+
+    /// The `errno` with which the command failed, if it did.
+    pub errno: Option<i32>,
+
+    /// The status reported by the firmware, where zero means the firmware
+    /// did not report an error.
+    pub status: u32,
+}
+
+/// The commands issued to a device, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Transcript {
+    entries: Vec<Entry>,
+}
+
+impl Transcript {
+    /// The recorded commands, in the order they were issued.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/// A device which records a [`Transcript`] of the commands issued through it
+/// to the device it wraps.
+///
+/// Only the command itself is recorded, not the buffers of the caller which
+/// the kernel reads or writes through the addresses a command holds, so only
+/// commands which are plain data can be recorded. Commands referring to such
+/// buffers borrow them, and so do not satisfy the `NoUninit` bound.
+#[derive(Debug)]
+pub struct Recorder<D> {
+    device: D,
+    transcript: Transcript,
+}
+
+impl<D> Recorder<D> {
+    /// Record the commands issued to `device`.
+    pub fn new(device: D) -> Self {
+        Self {
+            device,
+            transcript: Transcript::default(),
+        }
+    }
+
+    /// The commands recorded so far.
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Give back the wrapped device and the recorded commands.
+    pub fn into_inner(self) -> (D, Transcript) {
+        (self.device, self.transcript)
+    }
+}
+
+impl<D, T> SevDevice<T> for Recorder<D>
+where
+    D: SevDevice<T, Error = CommandError>,
+    T: Id + NoUninit,
+{
+    type Error = CommandError;
+
+    fn issue_ioctl(&mut self, subcmd: &mut T) -> Result<(), CommandError> {
+        let input = bytemuck::bytes_of(subcmd).to_vec();
+        let result = self.device.issue_ioctl(subcmd);
+
+        let (errno, status) = match &result {
+            Ok(()) => (None, 0),
+            Err(e) => (e.os_error().raw_os_error(), e.status()),
+        };
+
+        self.transcript.entries.push(Entry {
+            id: T::ID,
+            input,
+            output: bytemuck::bytes_of(subcmd).to_vec(),
+            errno,
+            status,
+        });
+
+        result
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::mock::{MockDevice, MockResponse};
+    use crate::sev::{GetId, PlatformStatus};
+
+    #[test]
+    fn record_and_replay() {
+        let mut device = MockDevice::new();
+        device
+            .respond(PlatformStatus::ID, MockResponse::Payload(vec![1, 52, 1]))
+            .fail(GetId::ID, 0x0b)
+            .respond(GetId::ID, MockResponse::Os(16));
+
+        let mut recorder = Recorder::new(device);
+        let mut status = PlatformStatus::default();
+        recorder.issue_ioctl(&mut status).unwrap();
+        assert!(recorder.issue_ioctl(&mut GetId::default()).is_err());
+        assert!(recorder.issue_ioctl(&mut GetId::default()).is_err());
+
+        let (_, transcript) = recorder.into_inner();
+        let entries = transcript.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].input, [0; 12]);
+        assert_eq!(entries[0].output, bytemuck::bytes_of(&status));
+        assert_eq!((entries[1].errno, entries[1].status), (Some(5), 0x0b));
+        assert_eq!((entries[2].errno, entries[2].status), (Some(16), 0));
+
+        let mut replay = MockDevice::replay(&transcript);
+        let mut replayed = PlatformStatus::default();
+        replay.issue_ioctl(&mut replayed).unwrap();
+        assert_eq!(replayed, status);
+
+        let err = replay.issue_ioctl(&mut GetId::default()).unwrap_err();
+        assert_eq!(err.status(), 0x0b);
+
+        let err = replay.issue_ioctl(&mut GetId::default()).unwrap_err();
+        assert_eq!(err.os_error().raw_os_error(), Some(16));
+        assert_eq!(replay.issued(), [PlatformStatus::ID, GetId::ID, GetId::ID]);
+    }
+}
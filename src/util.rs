@@ -1,20 +1,123 @@
 // SPDX-License-Identifier: Apache-2.0
 
 /// Helpful primitives for developing the sev and snp crates.
-use std::io::{Read, Result, Write};
+use crate::error::{Error, Indeterminate, InputError};
+
+use std::io::{Read, Result, Seek, SeekFrom, Write};
 use std::mem::{size_of, MaybeUninit};
 use std::slice::{from_raw_parts, from_raw_parts_mut};
 
+/// Implement an ID trait for each listed command type.
+///
+/// Each entry may be preceded by attributes, such as `#[cfg(...)]` or
+/// documentation, which are applied to everything generated for it.
+///
+/// Naming a marker trait, as in `pub Id: Marker => u32;`, also implements
+/// that trait for each command. Naming an enum with `as`, as in
+/// `pub Id => u32 as CommandCode;`, also generates that enum with a variant
+/// for every command, which requires each command to be named by a plain
+/// identifier.
 #[doc(hidden)]
 #[macro_export]
 macro_rules! impl_const_id {
+    (
+        $visibility:vis $trait:ident $(: $marker:path)? => $id_ty:ident as $code:ident;
+        $(
+            $(#[$attr:meta])*
+            $iocty:ident $(<$($lt:lifetime),+>)? = $val:expr
+        ),* $(,)*
+    ) => {
+        $crate::impl_const_id! {
+            $visibility $trait $(: $marker)? => $id_ty;
+            $(
+                $(#[$attr])*
+                $iocty $(<$($lt),+>)? = $val
+            ),*
+        }
+
+        /// The code of every command in this module, for mapping a code
+        /// reported alongside an error back to the command it belongs to.
+        #[repr($id_ty)]
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        $visibility enum $code {
+            $(
+                #[doc = concat!("The code of [`", stringify!($iocty), "`].")]
+                $(#[$attr])*
+                $iocty = $val,
+            )*
+        }
+
+        impl $code {
+            /// The name of the command.
+            #[allow(unused_doc_comments, unused_attributes)]
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        $code::$iocty => stringify!($iocty),
+                    )*
+                }
+            }
+        }
+
+        impl ::std::convert::TryFrom<$id_ty> for $code {
+            type Error = $id_ty;
+
+            #[allow(unused_doc_comments, unused_attributes)]
+            fn try_from(code: $id_ty) -> ::std::result::Result<Self, $id_ty> {
+                $(
+                    $(#[$attr])*
+                    if code == $val {
+                        return Ok($code::$iocty);
+                    }
+                )*
+                Err(code)
+            }
+        }
+
+        impl From<$code> for $id_ty {
+            fn from(code: $code) -> $id_ty {
+                code as $id_ty
+            }
+        }
+
+        impl ::std::fmt::Display for $code {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    };
+
+    (
+        $visibility:vis $trait:ident: $marker:path => $id_ty:ty;
+        $(
+            $(#[$attr:meta])*
+            $iocty:ty = $val:expr
+        ),* $(,)*
+    ) => {
+        $crate::impl_const_id! {
+            $visibility $trait => $id_ty;
+            $(
+                $(#[$attr])*
+                $iocty = $val
+            ),*
+        }
+
+        $(
+            $(#[$attr])*
+            impl $marker for $iocty {}
+        )*
+    };
+
     (
      	$visibility:vis $trait:ident => $id_ty:ty;
         $(
+            $(#[$attr:meta])*
             $iocty:ty = $val:expr
         ),* $(,)*
     ) => {
 	$(
+            $(#[$attr])*
             impl $trait for $iocty {
                 const ID: $id_ty = $val;
             }
@@ -22,14 +125,227 @@ macro_rules! impl_const_id {
     };
 }
 
+/// Implement `bytemuck::Pod` for each listed type when the `bytemuck`
+/// feature is enabled.
+///
+/// Every listed type must be `Copy` plain data without padding, for which
+/// every bit pattern is valid.
+macro_rules! impl_pod {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            #[cfg(feature = "bytemuck")]
+            unsafe impl bytemuck::Zeroable for $ty {}
+
+            #[cfg(feature = "bytemuck")]
+            unsafe impl bytemuck::Pod for $ty {}
+        )*
+    };
+}
+
+pub(crate) use impl_pod;
+
+/// Assert at build time that a type has the given size and alignment, and
+/// optionally that fields sit at the given offsets, so that a mismatch with the
+/// kernel UAPI headers fails to compile rather than surfacing as an `EFAULT` at
+/// runtime.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! assert_layout {
+    ($ty:ty, $size:expr, $align:expr $(, $field:ident @ $offset:expr)* $(,)?) => {
+        const _: () = assert!(::std::mem::size_of::<$ty>() == $size);
+        const _: () = assert!(::std::mem::align_of::<$ty>() == $align);
+        $(const _: () = assert!(::std::mem::offset_of!($ty, $field) == $offset);)*
+    };
+}
+
+/// A plain data type whose multi-byte integers can be reversed in byte order,
+/// so that it can be persisted in a fixed byte order.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid value of the implementing type, since
+/// values are read directly from bytes before being converted.
+pub unsafe trait SwapBytes: Copy {
+    /// Reverse the byte order of every multi-byte integer in the value.
+    fn swap_bytes(self) -> Self;
+
+    /// Convert the value between the native byte order and little endian.
+    fn to_le(self) -> Self {
+        if cfg!(target_endian = "little") {
+            self
+        } else {
+            self.swap_bytes()
+        }
+    }
+
+    /// Convert the value between the native byte order and big endian.
+    fn to_be(self) -> Self {
+        if cfg!(target_endian = "big") {
+            self
+        } else {
+            self.swap_bytes()
+        }
+    }
+}
+
+macro_rules! impl_swap_bytes {
+    ($($ty:ty),* $(,)*) => {
+        $(
+            unsafe impl SwapBytes for $ty {
+                fn swap_bytes(self) -> Self {
+                    <$ty>::swap_bytes(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_swap_bytes!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+unsafe impl<T: SwapBytes, const N: usize> SwapBytes for [T; N] {
+    fn swap_bytes(self) -> Self {
+        self.map(SwapBytes::swap_bytes)
+    }
+}
+
+fn load_raw<T: Copy, R: Read + ?Sized>(reader: &mut R) -> Result<T> {
+    let mut t = MaybeUninit::<T>::uninit();
+    let s = unsafe { from_raw_parts_mut(t.as_mut_ptr() as _, size_of::<T>()) };
+    reader.read_exact(s)?;
+    let t = unsafe { t.assume_init() };
+    Ok(t)
+}
+
 pub trait TypeLoad: Read {
+    /// Read a value of type `T` directly from its in-memory representation.
+    ///
+    /// This is unsound for types which have invalid bit patterns, such as
+    /// enums, `bool` or references, since reading them from arbitrary bytes
+    /// can produce an invalid value.
+    #[deprecated(note = "use `load_le`, or `load_pod` with the `bytemuck` feature")]
     fn load<T: Sized + Copy>(&mut self) -> Result<T> {
-        let mut t = MaybeUninit::<T>::uninit();
-        let s = unsafe { from_raw_parts_mut(t.as_mut_ptr() as _, size_of::<T>()) };
-        self.read_exact(s)?;
-        let t = unsafe { t.assume_init() };
+        load_raw(self)
+    }
+
+    /// Read a value of type `T` which was stored in little endian.
+    fn load_le<T: SwapBytes>(&mut self) -> Result<T> {
+        load_raw(self).map(SwapBytes::to_le)
+    }
+
+    /// Read a value of type `T` which was stored in big endian.
+    fn load_be<T: SwapBytes>(&mut self) -> Result<T> {
+        load_raw(self).map(SwapBytes::to_be)
+    }
+
+    /// Read `count` consecutive values of type `T`, such as a table of
+    /// repeated records.
+    fn load_vec<T: SwapBytes>(&mut self, count: usize) -> Result<Vec<T>> {
+        // The count is untrusted, so the values are read one by one rather
+        // than reserving room for all of them up front.
+        let mut v = Vec::new();
+        for _ in 0..count {
+            v.push(load_raw(self)?);
+        }
+
+        Ok(v)
+    }
+
+    /// Read a value of type `T` directly from its in-memory representation,
+    /// where every bit pattern is a valid `T`.
+    #[cfg(feature = "bytemuck")]
+    fn load_pod<T: bytemuck::Pod>(&mut self) -> Result<T> {
+        let mut t = T::zeroed();
+        self.read_exact(bytemuck::bytes_of_mut(&mut t))?;
         Ok(t)
     }
+
+    /// Read a value of type `T` stored in little endian at `offset` bytes from
+    /// the start of the source, like the fields of the structures defined by
+    /// the firmware.
+    fn load_at<T: SwapBytes>(&mut self, offset: u64) -> Result<T>
+    where
+        Self: Seek,
+    {
+        self.seek(SeekFrom::Start(offset))?;
+        self.load_le()
+    }
+
+    /// Limit the following reads to a section of `len` bytes, such as one
+    /// entry of a variable-length firmware blob.
+    fn take_exact(&mut self, len: usize) -> Bounded<&mut Self>
+    where
+        Self: Sized,
+    {
+        Bounded::new(self, len)
+    }
+}
+
+/// A reader limited to a section of known length.
+///
+/// Like [`Read::take`], the end of the section reads as the end of the input,
+/// so reading a value past it fails with
+/// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) from
+/// [`Read::read_exact`]. Unlike [`Read::take`], input which ends before the
+/// section does is an `UnexpectedEof` error too, so a truncated blob cannot
+/// pass for a shorter section.
+#[derive(Debug)]
+pub struct Bounded<R> {
+    inner: R,
+    remaining: usize,
+}
+
+impl<R: Read> Bounded<R> {
+    /// Limit reads from `inner` to `len` bytes.
+    pub fn new(inner: R, len: usize) -> Self {
+        Self {
+            inner,
+            remaining: len,
+        }
+    }
+
+    /// The number of bytes left in the section.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Discard the rest of the section, such as trailing reserved bytes.
+    pub fn skip(&mut self) -> Result<()> {
+        let len = self.remaining as u64;
+        let skipped = std::io::copy(&mut (&mut self.inner).take(len), &mut std::io::sink())?;
+        self.remaining = 0;
+
+        if skipped != len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(())
+    }
+
+    /// Give back the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Bounded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= n;
+        Ok(n)
+    }
 }
 
 pub trait TypeSave: Write {
@@ -38,7 +354,962 @@ pub trait TypeSave: Write {
         let s = unsafe { from_raw_parts(p, size_of::<T>()) };
         self.write_all(s)
     }
+
+    /// Write a value of type `T` in little endian.
+    fn save_le<T: SwapBytes>(&mut self, value: &T) -> Result<()> {
+        self.save(&value.to_le())
+    }
+
+    /// Write a value of type `T` in big endian.
+    fn save_be<T: SwapBytes>(&mut self, value: &T) -> Result<()> {
+        self.save(&value.to_be())
+    }
+
+    /// Write consecutive values of type `T`, such as a table of repeated
+    /// records.
+    fn save_slice<T: SwapBytes>(&mut self, values: &[T]) -> Result<()> {
+        let p = values.as_ptr() as *const u8;
+        let s = unsafe { from_raw_parts(p, std::mem::size_of_val(values)) };
+        self.write_all(s)
+    }
 }
 
 impl<T: Read> TypeLoad for T {}
 impl<T: Write> TypeSave for T {}
+
+/// Treat a firmware `INVALID_LEN` response as success.
+///
+/// Commands which return variable-length data are first issued without a
+/// buffer to learn the required length, which the firmware reports by updating
+/// the length field and failing with `INVALID_LEN`.
+pub(crate) fn probe<T, E: Into<Indeterminate<Error>>>(
+    result: std::result::Result<T, E>,
+) -> std::result::Result<(), Indeterminate<Error>> {
+    match result.map_err(Into::into) {
+        Ok(_) | Err(Indeterminate::Known(Error::InvalidLen)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check that a buffer starts and ends on a boundary of `page` bytes.
+pub(crate) fn check_pages(buf: &[u8], page: usize) -> std::result::Result<(), InputError> {
+    let addr = buf.as_ptr() as usize;
+    if addr % page != 0 {
+        return Err(InputError::Misaligned { addr, align: page });
+    }
+
+    if buf.len() % page != 0 {
+        return Err(InputError::BadLength {
+            len: buf.len(),
+            multiple: page,
+        });
+    }
+
+    Ok(())
+}
+
+/// Convert a length to the 32-bit length field of an ioctl structure,
+/// failing rather than truncating it.
+pub(crate) fn try_u32(len: usize) -> std::result::Result<u32, InputError> {
+    use std::convert::TryFrom;
+
+    u32::try_from(len).map_err(|_| InputError::TooLong {
+        len,
+        max: u32::MAX as usize,
+    })
+}
+
+/// Check that the length of a buffer fits in a 32-bit length field.
+pub(crate) fn try_len_u32<T>(buf: &[T]) -> std::result::Result<u32, InputError> {
+    try_u32(buf.len())
+}
+
+/// A zeroed, page-aligned buffer spanning a whole number of pages, as
+/// required by the firmware for many of the buffers exchanged with it.
+pub struct AlignedBuffer {
+    ptr: std::ptr::NonNull<u8>,
+    len: usize,
+}
+
+// The buffer uniquely owns its allocation, just like a `Vec<u8>`.
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// The alignment and granularity of the buffer.
+    pub const PAGE_SIZE: usize = 4096;
+
+    fn layout(len: usize) -> std::alloc::Layout {
+        std::alloc::Layout::from_size_align(len, Self::PAGE_SIZE).expect("buffer too large")
+    }
+
+    /// Allocate a zeroed buffer of at least `len` bytes, rounded up to a whole
+    /// number of pages.
+    pub fn new(len: usize) -> Self {
+        let len = len
+            .checked_next_multiple_of(Self::PAGE_SIZE)
+            .expect("buffer too large")
+            .max(Self::PAGE_SIZE);
+        let layout = Self::layout(len);
+
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let ptr =
+            std::ptr::NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+        Self { ptr, len }
+    }
+
+    /// Allocate a buffer holding a copy of `data`, padded with zeroes to a
+    /// whole number of pages.
+    pub fn from_slice(data: &[u8]) -> Self {
+        let mut buf = Self::new(data.len());
+        buf[..data.len()].copy_from_slice(data);
+        buf
+    }
+}
+
+impl std::ops::Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl std::ops::DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Clone for AlignedBuffer {
+    fn clone(&self) -> Self {
+        Self::from_slice(self)
+    }
+}
+
+impl std::fmt::Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AlignedBuffer")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) }
+    }
+}
+
+/// A buffer holding secret material in the clear, such as a key derived by
+/// the firmware, which is wiped when dropped.
+///
+/// Transport keys and launch secrets only ever pass through this crate wrapped
+/// or encrypted by the firmware or the guest owner, so the buffers of commands
+/// such as `LAUNCH_SECRET` or `SEND_START` are not wrapped in this type.
+///
+/// The wrapped value is overwritten with zeroes using volatile writes, so the
+/// compiler cannot elide the wipe. Copies made of the contents are not wiped.
+pub struct Sensitive<T: AsMut<[u8]>>(T);
+
+impl<T: AsMut<[u8]>> Sensitive<T> {
+    /// Take ownership of the secret `value`.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: AsMut<[u8]>> From<T> for Sensitive<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: AsMut<[u8]>> std::ops::Deref for Sensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: AsMut<[u8]>> std::ops::DerefMut for Sensitive<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: AsMut<[u8]>> std::fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Sensitive(..)")
+    }
+}
+
+impl<T: AsMut<[u8]>> Drop for Sensitive<T> {
+    fn drop(&mut self) {
+        for byte in self.0.as_mut() {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// The fixed-size header of a structure ending in a flexible array member of
+/// `Item`s, which records the number of items following it.
+///
+/// # Safety
+///
+/// Every bit pattern, including all zeroes, must be a valid value of both the
+/// header and `Item`, since a [`FamStruct`] is allocated zeroed and may be
+/// filled in by the kernel.
+pub unsafe trait FamHeader<Item: Copy>: Copy {
+    /// The largest number of items the length field of the header can hold.
+    const MAX_LEN: usize = usize::MAX;
+
+    /// The number of items recorded by the header.
+    fn count(&self) -> usize;
+
+    /// Record the number of items in the header.
+    fn set_count(&mut self, count: usize);
+}
+
+/// A structure made of a header `H` followed by a variable number of `I`s,
+/// like the C structures ending in a flexible array member.
+///
+/// The whole structure is kept in a single allocation so that a pointer to it
+/// can be handed to the kernel, while the length field of the header is kept
+/// in step with the number of items allocated.
+pub struct FamStruct<H: FamHeader<I>, I: Copy> {
+    ptr: std::ptr::NonNull<u8>,
+    capacity: usize,
+    phantom: std::marker::PhantomData<(H, I)>,
+}
+
+unsafe impl<H: FamHeader<I> + Send, I: Copy + Send> Send for FamStruct<H, I> {}
+unsafe impl<H: FamHeader<I> + Sync, I: Copy + Sync> Sync for FamStruct<H, I> {}
+
+impl<H: FamHeader<I>, I: Copy> FamStruct<H, I> {
+    fn layout(capacity: usize) -> (std::alloc::Layout, usize) {
+        let items = std::alloc::Layout::array::<I>(capacity).expect("structure too large");
+        let (layout, offset) = std::alloc::Layout::new::<H>()
+            .extend(items)
+            .expect("structure too large");
+        (layout.pad_to_align(), offset)
+    }
+
+    fn check_len(len: usize) -> std::result::Result<(), InputError> {
+        if len > H::MAX_LEN {
+            return Err(InputError::TooLong {
+                len,
+                max: H::MAX_LEN,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Allocate a zeroed structure with room for `len` items, recording `len`
+    /// in its header.
+    pub fn new(len: usize) -> std::result::Result<Self, InputError> {
+        Self::check_len(len)?;
+
+        let (layout, _) = Self::layout(len);
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let ptr =
+            std::ptr::NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+
+        let mut fam = Self {
+            ptr,
+            capacity: len,
+            phantom: std::marker::PhantomData,
+        };
+        fam.header_mut().set_count(len);
+        Ok(fam)
+    }
+
+    /// Allocate a structure holding `header` followed by a copy of `items`,
+    /// whose length replaces the one recorded in `header`.
+    pub fn from_slice(header: H, items: &[I]) -> std::result::Result<Self, InputError> {
+        let mut fam = Self::new(items.len())?;
+        *fam.header_mut() = header;
+        fam.header_mut().set_count(items.len());
+        fam.items_mut().copy_from_slice(items);
+        Ok(fam)
+    }
+
+    /// The header of the structure.
+    pub fn header(&self) -> &H {
+        unsafe { &*(self.ptr.as_ptr() as *const H) }
+    }
+
+    /// The header of the structure, whose length field should only be changed
+    /// through [`FamStruct::resize`].
+    pub fn header_mut(&mut self) -> &mut H {
+        unsafe { &mut *(self.ptr.as_ptr() as *mut H) }
+    }
+
+    /// The number of items recorded by the header, bounded by the number of
+    /// items allocated in case the kernel asked for more room.
+    pub fn len(&self) -> usize {
+        self.header().count().min(self.capacity)
+    }
+
+    /// Whether the structure holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of items allocated.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The items following the header.
+    pub fn items(&self) -> &[I] {
+        let (_, offset) = Self::layout(self.capacity);
+        let p = unsafe { self.ptr.as_ptr().add(offset) } as *const I;
+        unsafe { from_raw_parts(p, self.len()) }
+    }
+
+    /// The items following the header.
+    pub fn items_mut(&mut self) -> &mut [I] {
+        let (_, offset) = Self::layout(self.capacity);
+        let p = unsafe { self.ptr.as_ptr().add(offset) } as *mut I;
+        unsafe { from_raw_parts_mut(p, self.len()) }
+    }
+
+    /// Change the number of items to `len`, keeping the header and the items
+    /// which still fit, and zeroing any new items.
+    ///
+    /// This is typically used when the kernel reports, through the length
+    /// field of the header, that more room is needed.
+    pub fn resize(&mut self, len: usize) -> std::result::Result<(), InputError> {
+        Self::check_len(len)?;
+        let old_len = self.len();
+
+        if len > self.capacity {
+            let (old, _) = Self::layout(self.capacity);
+            let (new, _) = Self::layout(len);
+
+            let ptr = unsafe { std::alloc::realloc(self.ptr.as_ptr(), old, new.size()) };
+            let ptr =
+                std::ptr::NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(new));
+            unsafe {
+                ptr.as_ptr()
+                    .add(old.size())
+                    .write_bytes(0, new.size() - old.size())
+            };
+
+            self.ptr = ptr;
+            self.capacity = len;
+        }
+
+        self.header_mut().set_count(len);
+        if len > old_len {
+            let new = &mut self.items_mut()[old_len..];
+            unsafe { new.as_mut_ptr().write_bytes(0, new.len()) };
+        }
+
+        Ok(())
+    }
+
+    /// A pointer to the start of the structure, to be passed to the kernel.
+    pub fn as_ptr(&self) -> *const H {
+        self.ptr.as_ptr() as *const H
+    }
+
+    /// A mutable pointer to the start of the structure, to be passed to the
+    /// kernel.
+    pub fn as_mut_ptr(&mut self) -> *mut H {
+        self.ptr.as_ptr() as *mut H
+    }
+}
+
+impl<H: FamHeader<I>, I: Copy> Clone for FamStruct<H, I> {
+    fn clone(&self) -> Self {
+        let mut fam = Self::new(self.capacity).expect("length already checked");
+        let (layout, _) = Self::layout(self.capacity);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.ptr.as_ptr(), fam.ptr.as_ptr(), layout.size());
+        }
+        fam.header_mut().set_count(self.header().count());
+        fam
+    }
+}
+
+impl<H: FamHeader<I> + std::fmt::Debug, I: Copy + std::fmt::Debug> std::fmt::Debug
+    for FamStruct<H, I>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FamStruct")
+            .field("header", self.header())
+            .field("items", &self.items())
+            .finish()
+    }
+}
+
+impl<H: FamHeader<I>, I: Copy> Drop for FamStruct<H, I> {
+    fn drop(&mut self) {
+        let (layout, _) = Self::layout(self.capacity);
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), layout) }
+    }
+}
+
+/// The address of a `T` in the caller's address space, as stored in the
+/// `u64` address fields of the structures passed to the kernel.
+///
+/// The address is taken with `<*const T>::expose_provenance`,
+/// so that the kernel (or the firmware, through it) may access the memory
+/// even though it does so outside of the Rust abstract machine. A `UserPtr`
+/// does not borrow the memory it points to: the structure holding it is
+/// responsible for tying the memory's lifetime to its own, usually with a
+/// `PhantomData` reference.
+#[repr(transparent)]
+pub struct UserPtr<T: ?Sized>(u64, std::marker::PhantomData<*const T>);
+
+// A `UserPtr` is only an integer and is never dereferenced by this crate.
+unsafe impl<T: ?Sized> Send for UserPtr<T> {}
+unsafe impl<T: ?Sized> Sync for UserPtr<T> {}
+
+impl<T: ?Sized> UserPtr<T> {
+    /// The null address, used by the kernel to represent an absent buffer.
+    pub const fn null() -> Self {
+        Self(0, std::marker::PhantomData)
+    }
+
+    /// The address of the memory behind `ptr`, exposing its provenance.
+    pub fn from_ptr(ptr: *const T) -> Self {
+        Self(
+            ptr.cast::<u8>().expose_provenance() as u64,
+            std::marker::PhantomData,
+        )
+    }
+
+    /// The address of memory the kernel will only read from.
+    pub fn from_ref(value: &T) -> Self {
+        Self::from_ptr(value)
+    }
+
+    /// The address of memory the kernel may write to.
+    pub fn from_mut(value: &mut T) -> Self {
+        Self::from_ptr(value)
+    }
+
+    /// The numeric value of the address.
+    pub fn addr(self) -> u64 {
+        self.0
+    }
+
+    /// Whether this is the null address.
+    pub fn is_null(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl<T: ?Sized> Copy for UserPtr<T> {}
+
+impl<T: ?Sized> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Default for UserPtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T: ?Sized> PartialEq for UserPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: ?Sized> Eq for UserPtr<T> {}
+
+impl<T: ?Sized> std::hash::Hash for UserPtr<T> {
+    fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
+        self.0.hash(state)
+    }
+}
+
+impl<T: ?Sized> std::fmt::Debug for UserPtr<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "UserPtr({:#x})", self.0)
+    }
+}
+
+fn write_hexdump(f: &mut impl std::fmt::Write, bytes: &[u8]) -> std::fmt::Result {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if i > 0 {
+            f.write_char('\n')?;
+        }
+
+        write!(f, "{:08x}:", i * 16)?;
+        for byte in line {
+            write!(f, " {:02x}", byte)?;
+        }
+
+        write!(f, "{:1$} |", "", (16 - line.len()) * 3 + 1)?;
+        for &byte in line {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            f.write_char(c)?;
+        }
+        f.write_char('|')?;
+    }
+
+    Ok(())
+}
+
+/// Render `bytes` as a hexdump with one line of 16 bytes per offset, such as:
+///
+/// ```text
+/// 00000000: 53 45 56 43 01 00 00 00                          |SEVC....|
+/// ```
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    write_hexdump(&mut s, bytes).expect("writing to a string cannot fail");
+    s
+}
+
+/// A wrapper whose `Debug` output is a [`hexdump`] of the in-memory
+/// representation of the wrapped value, for inspecting the exact bytes of a
+/// command payload the firmware rejected.
+pub struct DebugBytes<'a, T: ?Sized>(&'a T);
+
+impl<'a, T: ?Sized> DebugBytes<'a, T> {
+    /// Wrap `value` to render its bytes.
+    ///
+    /// # Safety
+    ///
+    /// Every byte of `value` is read when it is formatted, so the caller must
+    /// ensure that `T` has no padding or other uninitialized bytes.
+    pub unsafe fn new(value: &'a T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: ?Sized> std::fmt::Debug for DebugBytes<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let p = self.0 as *const T as *const u8;
+        let bytes = unsafe { from_raw_parts(p, std::mem::size_of_val(self.0)) };
+        write_hexdump(f, bytes)
+    }
+}
+
+/// Storage for a value which the kernel fills in entirely, such as the
+/// response to a guest request, so that it need not be zeroed beforehand.
+///
+/// The storage can only be read through [`OutBuf::assume_init`], once the
+/// command which fills it has succeeded.
+#[repr(transparent)]
+pub struct OutBuf<T>(MaybeUninit<T>);
+
+impl<T> OutBuf<T> {
+    /// Create uninitialized storage for a `T`.
+    pub fn new() -> Self {
+        Self(MaybeUninit::uninit())
+    }
+
+    /// A pointer to the storage, to be written to by the kernel.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.0.as_mut_ptr()
+    }
+
+    /// Take the value written by the kernel.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the whole value has been written, which is
+    /// usually only the case once the command filling it has succeeded.
+    pub unsafe fn assume_init(self) -> T {
+        self.0.assume_init()
+    }
+}
+
+impl<T> Default for OutBuf<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> std::fmt::Debug for OutBuf<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("OutBuf(..)")
+    }
+}
+
+/// A fixed-size array of bytes, such as a measurement or a chip ID, which is
+/// displayed and serialized as hexadecimal.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ByteArray<const N: usize>(pub [u8; N]);
+
+// SAFETY: the array consists only of bytes, which have no byte order.
+unsafe impl<const N: usize> SwapBytes for ByteArray<N> {
+    fn swap_bytes(self) -> Self {
+        self
+    }
+}
+
+impl<const N: usize> ByteArray<N> {
+    /// The bytes of the array.
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+
+    /// Parse an array from exactly `2 * N` hexadecimal digits.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != N * 2 {
+            return None;
+        }
+
+        let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+        let mut bytes = [0u8; N];
+        for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+            *byte = digit(pair[0])? << 4 | digit(pair[1])?;
+        }
+
+        Some(Self(bytes))
+    }
+}
+
+impl<const N: usize> Default for ByteArray<N> {
+    fn default() -> Self {
+        Self([0; N])
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for ByteArray<N> {
+    fn from(bytes: [u8; N]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl<const N: usize> From<ByteArray<N>> for [u8; N] {
+    fn from(bytes: ByteArray<N>) -> Self {
+        bytes.0
+    }
+}
+
+impl<const N: usize> std::ops::Deref for ByteArray<N> {
+    type Target = [u8; N];
+
+    fn deref(&self) -> &[u8; N] {
+        &self.0
+    }
+}
+
+impl<const N: usize> std::ops::DerefMut for ByteArray<N> {
+    fn deref_mut(&mut self) -> &mut [u8; N] {
+        &mut self.0
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for ByteArray<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for ByteArray<N> {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        self.0 == *other
+    }
+}
+
+impl<const N: usize> std::fmt::Display for ByteArray<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for ByteArray<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ByteArray({})", self)
+    }
+}
+
+/// Serialized as a hexadecimal string by human-readable formats, and as bytes
+/// otherwise.
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for ByteArray<N> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> serde::Deserialize<'de> for ByteArray<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct Visitor<const N: usize>;
+
+        impl<'de, const N: usize> serde::de::Visitor<'de> for Visitor<N> {
+            type Value = ByteArray<N>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{} bytes or {} hexadecimal digits", N, N * 2)
+            }
+
+            fn visit_str<E: serde::de::Error>(
+                self,
+                v: &str,
+            ) -> std::result::Result<Self::Value, E> {
+                ByteArray::from_hex(v)
+                    .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+            }
+
+            fn visit_bytes<E: serde::de::Error>(
+                self,
+                v: &[u8],
+            ) -> std::result::Result<Self::Value, E> {
+                let bytes = <[u8; N] as std::convert::TryFrom<_>>::try_from(v)
+                    .map_err(|_| E::invalid_length(v.len(), &self))?;
+                Ok(ByteArray(bytes))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                let mut bytes = [0u8; N];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                }
+
+                Ok(ByteArray(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(Visitor)
+        } else {
+            deserializer.deserialize_bytes(Visitor)
+        }
+    }
+}
+
+/// Equality which takes the same time however many bytes match, for comparing
+/// measurements, digests and keys without leaking through timing how much of
+/// a secret or expected value was guessed correctly.
+///
+/// Prefer this over `==`, whose comparison may stop at the first mismatch.
+pub trait ConstantTimeEq {
+    /// Whether `self` and `other` are equal, compared in constant time.
+    fn ct_eq(&self, other: &Self) -> bool;
+}
+
+impl ConstantTimeEq for [u8] {
+    fn ct_eq(&self, other: &Self) -> bool {
+        // The length is not considered secret.
+        if self.len() != other.len() {
+            return false;
+        }
+
+        let diff = self
+            .iter()
+            .zip(other)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+        std::hint::black_box(diff) == 0
+    }
+}
+
+impl<const N: usize> ConstantTimeEq for [u8; N] {
+    fn ct_eq(&self, other: &Self) -> bool {
+        self[..].ct_eq(&other[..])
+    }
+}
+
+impl ConstantTimeEq for Vec<u8> {
+    fn ct_eq(&self, other: &Self) -> bool {
+        self[..].ct_eq(&other[..])
+    }
+}
+
+impl<const N: usize> ConstantTimeEq for ByteArray<N> {
+    fn ct_eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl<T: AsMut<[u8]> + ConstantTimeEq> ConstantTimeEq for Sensitive<T> {
+    fn ct_eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::ErrorKind;
+
+    #[test]
+    fn bounded_reads_to_end_of_section() {
+        let data = [1u8, 2, 3, 4, 5];
+        let mut input = &data[..];
+        let mut section = input.take_exact(3);
+
+        let mut bytes = Vec::new();
+        section.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3]);
+        assert_eq!(section.remaining(), 0);
+        assert_eq!(section.read(&mut [0; 1]).unwrap(), 0);
+        assert_eq!(input, [4, 5]);
+    }
+
+    #[test]
+    fn bounded_rejects_value_past_end() {
+        let data = [0u8; 8];
+        let mut input = &data[..];
+        let mut section = input.take_exact(3);
+
+        let err = section.load_le::<u32>().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn bounded_rejects_truncated_input() {
+        let data = [0u8; 2];
+        let mut section = Bounded::new(&data[..], 4);
+
+        let mut bytes = Vec::new();
+        let err = section.read_to_end(&mut bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn bounded_skips_rest_of_section() {
+        let data = [1u8, 2, 3, 4];
+        let mut input = &data[..];
+        let mut section = input.take_exact(3);
+
+        assert_eq!(section.load_le::<u8>().unwrap(), 1);
+        section.skip().unwrap();
+        assert_eq!(section.remaining(), 0);
+        assert_eq!(input, [4]);
+
+        let err = Bounded::new(&data[..], 8).skip().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    struct Header {
+        count: u32,
+        tag: u32,
+    }
+
+    unsafe impl FamHeader<u16> for Header {
+        const MAX_LEN: usize = 8;
+
+        fn count(&self) -> usize {
+            self.count as usize
+        }
+
+        fn set_count(&mut self, count: usize) {
+            self.count = count as u32;
+        }
+    }
+
+    fn fam(items: &[u16]) -> FamStruct<Header, u16> {
+        FamStruct::from_slice(Header { count: 0, tag: 7 }, items).unwrap()
+    }
+
+    #[test]
+    fn fam_resize_grows() {
+        let mut fam = fam(&[1, 2, 3]);
+        fam.resize(5).unwrap();
+
+        assert_eq!(fam.capacity(), 5);
+        assert_eq!(fam.header(), &Header { count: 5, tag: 7 });
+        assert_eq!(fam.items(), [1, 2, 3, 0, 0]);
+    }
+
+    #[test]
+    fn fam_resize_shrinks() {
+        let mut fam = fam(&[1, 2, 3]);
+        fam.resize(1).unwrap();
+
+        assert_eq!(fam.capacity(), 3);
+        assert_eq!(fam.header().count, 1);
+        assert_eq!(fam.items(), [1]);
+
+        fam.resize(3).unwrap();
+        assert_eq!(fam.items(), [1, 0, 0]);
+    }
+
+    #[test]
+    fn fam_rejects_too_long() {
+        let err = FamStruct::<Header, u16>::new(9).unwrap_err();
+        assert_eq!(err, InputError::TooLong { len: 9, max: 8 });
+
+        let mut fam = fam(&[1]);
+        assert_eq!(fam.resize(9).unwrap_err(), err);
+        assert_eq!(fam.items(), [1]);
+    }
+
+    #[test]
+    fn fam_clone_is_independent() {
+        let mut fam = fam(&[1, 2, 3]);
+        fam.resize(2).unwrap();
+        let copy = fam.clone();
+
+        fam.items_mut()[0] = 9;
+        fam.header_mut().tag = 8;
+
+        assert_eq!(copy.capacity(), 3);
+        assert_eq!(copy.header(), &Header { count: 2, tag: 7 });
+        assert_eq!(copy.items(), [1, 2]);
+    }
+
+    #[test]
+    fn fam_bounds_count_by_capacity() {
+        let mut fam = fam(&[1, 2]);
+        fam.header_mut().set_count(6);
+
+        assert_eq!(fam.len(), 2);
+        assert_eq!(fam.items(), [1, 2]);
+
+        let wanted = fam.header().count();
+        fam.resize(wanted).unwrap();
+        assert_eq!(fam.capacity(), 6);
+        assert_eq!(fam.items(), [1, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn load_vec_with_untrusted_count() {
+        let data = [1u8, 0, 2, 0, 3];
+
+        let values: Vec<u16> = (&data[..]).load_vec(2).unwrap();
+        assert_eq!(
+            values,
+            [u16::from_ne_bytes([1, 0]), u16::from_ne_bytes([2, 0])]
+        );
+
+        let error = (&data[..]).load_vec::<u16>(usize::MAX).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: Apache-2.0
+
+/// The save area of an SEV-ES/SEV-SNP vCPU (VMSA).
+use crate::assert_layout;
+
+/// A segment register as stored in the VMCB save area.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct VmcbSegment {
+    /// The segment selector.
+    pub selector: u16,
+
+    /// The segment attributes, in the compressed VMCB encoding.
+    pub attrib: u16,
+
+    /// The segment limit.
+    pub limit: u32,
+
+    /// The segment base address.
+    pub base: u64,
+}
+
+assert_layout!(VmcbSegment, 16, 1);
+
+impl VmcbSegment {
+    /// Create a new `VmcbSegment`.
+    pub const fn new(selector: u16, attrib: u16, limit: u32, base: u64) -> Self {
+        Self {
+            selector,
+            attrib,
+            limit,
+            base,
+        }
+    }
+}
+
+/// The VMCB save area of an SEV-ES/SEV-SNP vCPU, which is encrypted and
+/// integrity protected while the guest runs.
+///
+/// This struct is defined in the Linux kernel as `struct sev_es_save_area`:
+/// arch/x86/include/asm/svm.h
+///
+/// The default value encodes the architectural reset state of the bootstrap
+/// processor as KVM and QEMU establish it, which is what launch measurements
+/// are computed over. The `rdx` register (which holds the CPUID processor
+/// signature at reset) and `sev_features` depend on the host and the VM
+/// configuration, so they are left zeroed.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Vmsa {
+    pub es: VmcbSegment,
+    pub cs: VmcbSegment,
+    pub ss: VmcbSegment,
+    pub ds: VmcbSegment,
+    pub fs: VmcbSegment,
+    pub gs: VmcbSegment,
+    pub gdtr: VmcbSegment,
+    pub ldtr: VmcbSegment,
+    pub idtr: VmcbSegment,
+    pub tr: VmcbSegment,
+    pub vmpl0_ssp: u64,
+    pub vmpl1_ssp: u64,
+    pub vmpl2_ssp: u64,
+    pub vmpl3_ssp: u64,
+    pub u_cet: u64,
+    reserved_0xc8: [u8; 2],
+    pub vmpl: u8,
+    pub cpl: u8,
+    reserved_0xcc: [u8; 4],
+    pub efer: u64,
+    reserved_0xd8: [u8; 104],
+    pub xss: u64,
+    pub cr4: u64,
+    pub cr3: u64,
+    pub cr0: u64,
+    pub dr7: u64,
+    pub dr6: u64,
+    pub rflags: u64,
+    pub rip: u64,
+    pub dr0: u64,
+    pub dr1: u64,
+    pub dr2: u64,
+    pub dr3: u64,
+    pub dr0_addr_mask: u64,
+    pub dr1_addr_mask: u64,
+    pub dr2_addr_mask: u64,
+    pub dr3_addr_mask: u64,
+    reserved_0x1c0: [u8; 24],
+    pub rsp: u64,
+    pub s_cet: u64,
+    pub ssp: u64,
+    pub isst_addr: u64,
+    pub rax: u64,
+    pub star: u64,
+    pub lstar: u64,
+    pub cstar: u64,
+    pub sfmask: u64,
+    pub kernel_gs_base: u64,
+    pub sysenter_cs: u64,
+    pub sysenter_esp: u64,
+    pub sysenter_eip: u64,
+    pub cr2: u64,
+    reserved_0x248: [u8; 32],
+    pub g_pat: u64,
+    pub dbgctl: u64,
+    pub br_from: u64,
+    pub br_to: u64,
+    pub last_excp_from: u64,
+    pub last_excp_to: u64,
+    reserved_0x298: [u8; 80],
+    pub pkru: u32,
+    pub tsc_aux: u32,
+    reserved_0x2f0: [u8; 24],
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rbx: u64,
+    reserved_0x320: u64,
+    pub rbp: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    reserved_0x380: [u8; 16],
+    pub guest_exit_info_1: u64,
+    pub guest_exit_info_2: u64,
+    pub guest_exit_int_info: u64,
+    pub guest_nrip: u64,
+    pub sev_features: u64,
+    pub vintr_ctrl: u64,
+    pub guest_exit_code: u64,
+    pub virtual_tom: u64,
+    pub tlb_id: u64,
+    pub pcpu_id: u64,
+    pub event_inj: u64,
+    pub xcr0: u64,
+    reserved_0x3f0: [u8; 16],
+    pub x87_dp: u64,
+    pub mxcsr: u32,
+    pub x87_ftw: u16,
+    pub x87_fsw: u16,
+    pub x87_fcw: u16,
+    pub x87_fop: u16,
+    pub x87_ds: u16,
+    pub x87_cs: u16,
+    pub x87_rip: u64,
+    pub fpreg_x87: [u8; 80],
+    pub fpreg_xmm: [u8; 256],
+    pub fpreg_ymm: [u8; 256],
+    reserved_0x670: [u8; 2448],
+}
+
+assert_layout!(
+    Vmsa,
+    4096,
+    1,
+    rip @ 0x178,
+    sev_features @ 0x3B0,
+    xcr0 @ 0x3E8,
+    reserved_0x670 @ 0x670,
+);
+
+impl Default for Vmsa {
+    fn default() -> Self {
+        let data = VmcbSegment::new(0, 0x93, 0xffff, 0);
+
+        Self {
+            es: data,
+            cs: VmcbSegment::new(0xf000, 0x9b, 0xffff, 0xffff0000),
+            ss: data,
+            ds: data,
+            fs: data,
+            gs: data,
+            gdtr: VmcbSegment::new(0, 0, 0xffff, 0),
+            ldtr: VmcbSegment::new(0, 0x82, 0xffff, 0),
+            idtr: VmcbSegment::new(0, 0, 0xffff, 0),
+            tr: VmcbSegment::new(0, 0x8b, 0xffff, 0),
+            vmpl0_ssp: 0,
+            vmpl1_ssp: 0,
+            vmpl2_ssp: 0,
+            vmpl3_ssp: 0,
+            u_cet: 0,
+            reserved_0xc8: [0; 2],
+            vmpl: 0,
+            cpl: 0,
+            reserved_0xcc: [0; 4],
+            efer: 0x1000,
+            reserved_0xd8: [0; 104],
+            xss: 0,
+            cr4: 0x40,
+            cr3: 0,
+            cr0: 0x10,
+            dr7: 0x400,
+            dr6: 0xffff0ff0,
+            rflags: 0x2,
+            rip: 0xfff0,
+            dr0: 0,
+            dr1: 0,
+            dr2: 0,
+            dr3: 0,
+            dr0_addr_mask: 0,
+            dr1_addr_mask: 0,
+            dr2_addr_mask: 0,
+            dr3_addr_mask: 0,
+            reserved_0x1c0: [0; 24],
+            rsp: 0,
+            s_cet: 0,
+            ssp: 0,
+            isst_addr: 0,
+            rax: 0,
+            star: 0,
+            lstar: 0,
+            cstar: 0,
+            sfmask: 0,
+            kernel_gs_base: 0,
+            sysenter_cs: 0,
+            sysenter_esp: 0,
+            sysenter_eip: 0,
+            cr2: 0,
+            reserved_0x248: [0; 32],
+            g_pat: 0x0007040600070406,
+            dbgctl: 0,
+            br_from: 0,
+            br_to: 0,
+            last_excp_from: 0,
+            last_excp_to: 0,
+            reserved_0x298: [0; 80],
+            pkru: 0,
+            tsc_aux: 0,
+            reserved_0x2f0: [0; 24],
+            rcx: 0,
+            rdx: 0,
+            rbx: 0,
+            reserved_0x320: 0,
+            rbp: 0,
+            rsi: 0,
+            rdi: 0,
+            r8: 0,
+            r9: 0,
+            r10: 0,
+            r11: 0,
+            r12: 0,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+            reserved_0x380: [0; 16],
+            guest_exit_info_1: 0,
+            guest_exit_info_2: 0,
+            guest_exit_int_info: 0,
+            guest_nrip: 0,
+            sev_features: 0,
+            vintr_ctrl: 0,
+            guest_exit_code: 0,
+            virtual_tom: 0,
+            tlb_id: 0,
+            pcpu_id: 0,
+            event_inj: 0,
+            xcr0: 0x1,
+            reserved_0x3f0: [0; 16],
+            x87_dp: 0,
+            mxcsr: 0x1f80,
+            x87_ftw: 0,
+            x87_fsw: 0,
+            x87_fcw: 0x37f,
+            x87_fop: 0,
+            x87_ds: 0,
+            x87_cs: 0,
+            x87_rip: 0,
+            fpreg_x87: [0; 80],
+            fpreg_xmm: [0; 256],
+            fpreg_ymm: [0; 256],
+            reserved_0x670: [0; 2448],
+        }
+    }
+}